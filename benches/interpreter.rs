@@ -0,0 +1,61 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use mini_lisp::{interpreter, parser, vm};
+
+const FIB: &str = r"(define fib (fun (x)
+  (if (< x 2) x (+
+    (fib (- x 1))
+    (fib (- x 2))))))
+(print-num (fib 25))";
+
+const LOOP: &str = r"(define loop (fun (n acc)
+  (if (= n 0) acc (loop (- n 1) (+ acc 1)))))
+(print-num (loop 100000 0))";
+
+const CLOSURES: &str = r"(define compose (fun (f g) (fun (x) (f (g x)))))
+(define add-one (fun (x) (+ x 1)))
+(define twice (fun (f) (compose f f)))
+(define run (fun (n acc)
+  (define step (twice add-one))
+  (if (= n 0) acc (run (- n 1) (step acc)))))
+(print-num (run 20000 0))";
+
+const INTERNAL_DEFINES: &str = r"(define dist-square (fun (x y)
+  (define square (fun (n) (* n n)))
+  (define sx (square x))
+  (define sy (square y))
+  (+ sx sy)))
+(define sum (fun (n acc)
+  (if (= n 0) acc (sum (- n 1) (+ acc (dist-square n 2))))))
+(print-num (sum 20000 0))";
+
+fn bench(c: &mut Criterion, name: &str, source: &str) {
+    let program = parser::parse(source).unwrap();
+    c.bench_function(&format!("{} (tree)", name), |b| {
+        b.iter(|| {
+            let mut writer = Vec::new();
+            interpreter::run(program.clone(), &mut writer).unwrap();
+            writer
+        })
+    });
+    c.bench_function(&format!("{} (vm)", name), |b| {
+        b.iter(|| {
+            let mut writer = Vec::new();
+            vm::run(program.clone(), &mut writer).unwrap();
+            writer
+        })
+    });
+}
+
+fn programs(c: &mut Criterion) {
+    bench(c, "fib 25", FIB);
+    bench(c, "tail loop", LOOP);
+    bench(c, "closures", CLOSURES);
+    bench(c, "internal defines", INTERNAL_DEFINES);
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().sample_size(10);
+    targets = programs
+}
+criterion_main!(benches);
@@ -1,28 +1,91 @@
 #![allow(dead_code)]
 
+use std::fmt;
+use std::rc::Rc;
+
+use crate::number::Number;
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct Program {
     pub stmts: Vec<Stmt>,
+    /// Every comment outside of another comment, in source order. They do not
+    /// affect what the program means.
+    pub comments: Vec<Comment>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comment {
+    pub kind: CommentKind,
+    /// The comment as written, including its delimiters.
+    pub text: String,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CommentKind {
+    /// `; ...` up to the end of the line.
+    Line,
+    /// `#| ... |#`, which may contain other block comments.
+    Block,
+    /// `#;` followed by the expression it comments out.
+    Datum,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub col: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize, line: usize, col: usize) -> Self {
+        Span { start, end, line, col }
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.col)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stmt {
+    pub kind: StmtKind,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone, PartialEq)]
-pub enum Stmt {
+pub enum StmtKind {
     ExpStmt { exp: Exp },
     PrintStmt { print_type: PrintType, exp: Exp },
     DefStmt { id: Exp, exp: Exp },
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum PrintType {
     PrintNum,
     PrintBool,
 }
 
 #[derive(Debug, Clone, PartialEq)]
-pub enum Exp {
+pub struct Exp {
+    pub kind: ExpKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExpKind {
     Bool(bool),
-    Num(i64),
+    Num(Number),
     Id(String),
+    /// An `Id` whose binding the resolver has located.
+    Var {
+        name: String,
+        addr: Addr,
+    },
     NumExp {
         op: NumOp,
         args: Vec<Box<Exp>>,
@@ -31,11 +94,8 @@ pub enum Exp {
         op: LogicalOp,
         args: Vec<Box<Exp>>,
     },
-    FunExp {
-        params: Vec<Exp>,
-        def_stmts: Vec<Stmt>,
-        body: Box<Exp>,
-    },
+    /// Shared with every closure made from it, so calls never copy the body.
+    FunExp(Rc<Lambda>),
     FunCall {
         func: Box<Exp>,
         args: Vec<Box<Exp>>,
@@ -47,18 +107,78 @@ pub enum Exp {
     },
 }
 
-impl Exp {
-    pub fn to_string(&self) -> String {
-        match self {
-            Exp::Bool(val) => val.to_string(),
-            Exp::Num(val) => val.to_string(),
-            Exp::Id(val) => val.to_string(),
-            _ => unimplemented!(),
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lambda {
+    /// The name the function is defined under, if it is the value of a `define`.
+    pub name: Option<String>,
+    pub params: Vec<Exp>,
+    pub def_stmts: Vec<Stmt>,
+    pub body: Exp,
+    /// Names of the call frame's slots, parameters first, filled in by the resolver.
+    pub slots: Rc<[String]>,
+}
+
+/// Where a resolved variable lives at run time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Addr {
+    /// Slot `slot` of the call frame `depth` functions out from the current one.
+    Local { depth: usize, slot: usize },
+    Global(usize),
+}
+
+/// Writes `(head arg ...)`, separating the parts with single spaces.
+fn write_form<T: fmt::Display>(f: &mut fmt::Formatter, head: &dyn fmt::Display, args: &[T]) -> fmt::Result {
+    write!(f, "({}", head)?;
+    for arg in args {
+        write!(f, " {}", arg)?;
+    }
+    write!(f, ")")
+}
+
+/// Prints the expression back as mini-lisp source, on a single line.
+impl fmt::Display for Exp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.kind {
+            ExpKind::Bool(val) => write!(f, "{}", if *val { "#t" } else { "#f" }),
+            ExpKind::Num(val) => write!(f, "{}", val),
+            ExpKind::Id(val) | ExpKind::Var { name: val, .. } => write!(f, "{}", val),
+            ExpKind::NumExp { op, args } => write_form(f, op, args),
+            ExpKind::LogicalExp { op, args } => write_form(f, op, args),
+            ExpKind::IfExp { cond_exp, then_exp, else_exp } => write!(f, "(if {} {} {})", cond_exp, then_exp, else_exp),
+            ExpKind::FunExp(lambda) => {
+                let params: Vec<String> = lambda.params.iter().map(|param| param.to_string()).collect();
+                write!(f, "(fun ({})", params.join(" "))?;
+                for stmt in &lambda.def_stmts {
+                    write!(f, " {}", stmt)?;
+                }
+                write!(f, " {})", lambda.body)
+            }
+            ExpKind::FunCall { func, args } => write_form(f, func, args),
         }
     }
 }
 
-#[derive(Debug, Clone, PartialEq)]
+impl fmt::Display for Stmt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.kind {
+            StmtKind::ExpStmt { exp } => write!(f, "{}", exp),
+            StmtKind::PrintStmt { print_type, exp } => write!(f, "({} {})", Operator::Print(*print_type), exp),
+            StmtKind::DefStmt { id, exp } => write!(f, "(define {} {})", id, exp),
+        }
+    }
+}
+
+/// One statement per line.
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for stmt in &self.stmts {
+            writeln!(f, "{}", stmt)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum NumOp {
     Plus,
     Minus,
@@ -68,11 +188,108 @@ pub enum NumOp {
     Greater,
     Smaller,
     Equal,
+    ToInexact,
+    ToExact,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum LogicalOp {
     And,
     Or,
     Not,
 }
+
+impl fmt::Display for NumOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            NumOp::Plus => "+",
+            NumOp::Minus => "-",
+            NumOp::Multiply => "*",
+            NumOp::Divide => "/",
+            NumOp::Modulus => "mod",
+            NumOp::Greater => ">",
+            NumOp::Smaller => "<",
+            NumOp::Equal => "=",
+            NumOp::ToInexact => "exact->inexact",
+            NumOp::ToExact => "exact",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl fmt::Display for LogicalOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            LogicalOp::And => "and",
+            LogicalOp::Or => "or",
+            LogicalOp::Not => "not",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Anything that only accepts arguments of one type.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operator {
+    Num(NumOp),
+    Logical(LogicalOp),
+    If,
+    Print(PrintType),
+}
+
+impl Operator {
+    /// Every operator, in the order compiled code numbers them.
+    pub const ALL: [Operator; 16] = [
+        Operator::Num(NumOp::Plus),
+        Operator::Num(NumOp::Minus),
+        Operator::Num(NumOp::Multiply),
+        Operator::Num(NumOp::Divide),
+        Operator::Num(NumOp::Modulus),
+        Operator::Num(NumOp::Greater),
+        Operator::Num(NumOp::Smaller),
+        Operator::Num(NumOp::Equal),
+        Operator::Num(NumOp::ToInexact),
+        Operator::Num(NumOp::ToExact),
+        Operator::Logical(LogicalOp::And),
+        Operator::Logical(LogicalOp::Or),
+        Operator::Logical(LogicalOp::Not),
+        Operator::If,
+        Operator::Print(PrintType::PrintNum),
+        Operator::Print(PrintType::PrintBool),
+    ];
+
+    pub fn index(self) -> usize {
+        Operator::ALL.iter().position(|known| *known == self).unwrap()
+    }
+}
+
+impl fmt::Display for Operator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Operator::Num(op) => write!(f, "{}", op),
+            Operator::Logical(op) => write!(f, "{}", op),
+            Operator::If => write!(f, "if"),
+            Operator::Print(PrintType::PrintNum) => write!(f, "print-num"),
+            Operator::Print(PrintType::PrintBool) => write!(f, "print-bool"),
+        }
+    }
+}
+
+/// Argument `position` of `operator`, counting from 1, which a type error is about.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Operand {
+    pub operator: Operator,
+    pub position: usize,
+}
+
+impl Operand {
+    pub fn new(operator: Operator, position: usize) -> Self {
+        Operand { operator, position }
+    }
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "`{}` argument {}", self.operator, self.position)
+    }
+}
@@ -0,0 +1,395 @@
+//! The `.lspc` file format: a compiled `Module` written as little-endian binary.
+//!
+//! ```text
+//! "LSPC"  u16 version  u64 checksum of the rest  module
+//! ```
+//!
+//! Strings and sequences are prefixed with a `u32` length. Every instruction keeps
+//! its source span, so runtime errors still point into the original file.
+
+use std::rc::Rc;
+
+use crate::ast::*;
+use crate::compiler::{CallSite, Module, Op, Proto};
+use crate::error::Error;
+use crate::number::Number;
+
+pub const MAGIC: &[u8; 4] = b"LSPC";
+pub const VERSION: u16 = 3;
+
+pub fn encode(module: &Module) -> Vec<u8> {
+    let mut payload = Encoder::default();
+    payload.string(&module.source);
+    payload.u32(module.globals.len() as u32);
+    module.globals.iter().for_each(|name| payload.string(name));
+    payload.u32(module.stmts.len() as u32);
+    module.stmts.iter().for_each(|proto| payload.proto(proto));
+
+    let mut out = MAGIC.to_vec();
+    out.extend(VERSION.to_le_bytes());
+    out.extend(checksum(&payload.out).to_le_bytes());
+    out.extend(payload.out);
+    out
+}
+
+pub fn decode(bytes: &[u8]) -> Result<Module, Error> {
+    if !bytes.starts_with(MAGIC) {
+        return Err(invalid("not a mini-lisp bytecode file"));
+    }
+    let mut header = Decoder { bytes: &bytes[MAGIC.len()..] };
+    let version = header.u16()?;
+    if version != VERSION {
+        return Err(invalid(&format!(
+            "bytecode version {} is not supported (expected {}); recompile the source",
+            version, VERSION
+        )));
+    }
+    let sum = header.u64()?;
+    if checksum(header.bytes) != sum {
+        return Err(invalid("checksum mismatch, the file is corrupt"));
+    }
+
+    let mut decoder = header;
+    let source = decoder.string()?;
+    let globals = decoder.seq(|decoder| decoder.string())?;
+    let stmts = decoder.seq(|decoder| decoder.proto(&mut Vec::new(), false, globals.len()).map(Rc::new))?;
+    if !decoder.bytes.is_empty() {
+        return Err(corrupt());
+    }
+    Ok(Module { source, globals, stmts })
+}
+
+fn invalid(message: &str) -> Error {
+    Error::Bytecode {
+        message: message.to_string(),
+    }
+}
+
+fn corrupt() -> Error {
+    invalid("the file is truncated or corrupt")
+}
+
+/// 64-bit FNV-1a.
+fn checksum(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| (hash ^ *byte as u64).wrapping_mul(0x100000001b3))
+}
+
+const NUM_OPS: [NumOp; 10] = [
+    NumOp::Plus,
+    NumOp::Minus,
+    NumOp::Multiply,
+    NumOp::Divide,
+    NumOp::Modulus,
+    NumOp::Greater,
+    NumOp::Smaller,
+    NumOp::Equal,
+    NumOp::ToInexact,
+    NumOp::ToExact,
+];
+
+#[derive(Default)]
+struct Encoder {
+    out: Vec<u8>,
+}
+
+impl Encoder {
+    fn u8(&mut self, val: u8) {
+        self.out.push(val);
+    }
+
+    fn u32(&mut self, val: u32) {
+        self.out.extend(val.to_le_bytes());
+    }
+
+    fn u64(&mut self, val: u64) {
+        self.out.extend(val.to_le_bytes());
+    }
+
+    fn string(&mut self, val: &str) {
+        self.u32(val.len() as u32);
+        self.out.extend(val.as_bytes());
+    }
+
+    fn span(&mut self, span: &Span) {
+        [span.start, span.end, span.line, span.col].iter().for_each(|val| self.u32(*val as u32));
+    }
+
+    fn number(&mut self, num: &Number) {
+        match num {
+            Number::Float(val) => {
+                self.u8(1);
+                self.u64(val.to_bits());
+            }
+            _ => {
+                self.u8(0);
+                self.string(&num.to_string());
+            }
+        }
+    }
+
+    fn op(&mut self, op: &Op) {
+        let (code, operands): (u8, &[u32]) = match op {
+            Op::Const(index) => (0, &[*index]),
+            Op::Bool(val) => (1, &[*val as u32]),
+            Op::LoadLocal(depth, slot) => (2, &[*depth, *slot]),
+            Op::LoadGlobal(slot) => (3, &[*slot]),
+            Op::StoreLocal(depth, slot) => (4, &[*depth, *slot]),
+            Op::StoreGlobal(slot) => (5, &[*slot]),
+            Op::CheckNum(operand) => {
+                self.u8(6);
+                self.operand(operand);
+                return;
+            }
+            Op::CheckBool(operand) => {
+                self.u8(7);
+                self.operand(operand);
+                return;
+            }
+            Op::Arith(op, argc) => {
+                let op = NUM_OPS.iter().position(|known| known == op).unwrap() as u32;
+                self.u8(8);
+                self.u32(op);
+                self.u32(*argc);
+                return;
+            }
+            Op::Not => (9, &[]),
+            Op::Jump(target) => (10, &[*target]),
+            Op::JumpIfFalse(target) => (11, &[*target]),
+            Op::JumpIfTrue(target) => (12, &[*target]),
+            Op::Closure(index) => (13, &[*index]),
+            Op::CheckCall(site) => (14, &[*site]),
+            Op::Call(site) => (15, &[*site]),
+            Op::TailCall(site) => (16, &[*site]),
+            Op::PrintNum => (17, &[]),
+            Op::PrintBool => (18, &[]),
+            Op::Return => (19, &[]),
+        };
+        self.u8(code);
+        operands.iter().for_each(|operand| self.u32(*operand));
+    }
+
+    fn operand(&mut self, operand: &Operand) {
+        self.u8(operand.operator.index() as u8);
+        self.u32(operand.position as u32);
+    }
+
+    fn call_site(&mut self, site: &CallSite) {
+        self.name(&site.name);
+        self.u32(site.argc);
+        self.span(&site.span);
+        self.span(&site.func_span);
+        match &site.check {
+            Some((operand, span)) => {
+                self.u8(1);
+                self.operand(operand);
+                self.span(span);
+            }
+            None => self.u8(0),
+        }
+    }
+
+    fn name(&mut self, name: &Option<String>) {
+        match name {
+            Some(name) => {
+                self.u8(1);
+                self.string(name);
+            }
+            None => self.u8(0),
+        }
+    }
+
+    fn proto(&mut self, proto: &Proto) {
+        self.name(&proto.name);
+        self.u32(proto.arity);
+        self.u32(proto.slots.len() as u32);
+        proto.slots.iter().for_each(|slot| self.string(slot));
+        self.u32(proto.code.len() as u32);
+        proto.code.iter().for_each(|op| self.op(op));
+        proto.spans.iter().for_each(|span| self.span(span));
+        self.u32(proto.consts.len() as u32);
+        proto.consts.iter().for_each(|num| self.number(num));
+        self.u32(proto.protos.len() as u32);
+        proto.protos.iter().for_each(|proto| self.proto(proto));
+        self.u32(proto.calls.len() as u32);
+        proto.calls.iter().for_each(|site| self.call_site(site));
+    }
+}
+
+struct Decoder<'a> {
+    bytes: &'a [u8],
+}
+
+impl Decoder<'_> {
+    fn take(&mut self, len: usize) -> Result<&[u8], Error> {
+        if self.bytes.len() < len {
+            return Err(corrupt());
+        }
+        let (head, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(head)
+    }
+
+    fn u8(&mut self) -> Result<u8, Error> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, Error> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, Error> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, Error> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn flag(&mut self) -> Result<bool, Error> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(corrupt()),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, Error> {
+        let len = self.u32()? as usize;
+        String::from_utf8(self.take(len)?.to_vec()).map_err(|_| corrupt())
+    }
+
+    fn seq<T>(&mut self, mut item: impl FnMut(&mut Self) -> Result<T, Error>) -> Result<Vec<T>, Error> {
+        let len = self.u32()? as usize;
+        // Every item takes at least a byte, so a length beyond the input is corrupt.
+        if len > self.bytes.len() {
+            return Err(corrupt());
+        }
+        (0..len).map(|_| item(self)).collect()
+    }
+
+    fn span(&mut self) -> Result<Span, Error> {
+        let [start, end, line, col] = [self.u32()?, self.u32()?, self.u32()?, self.u32()?].map(|val| val as usize);
+        if start > end || line == 0 || col == 0 {
+            return Err(corrupt());
+        }
+        Ok(Span::new(start, end, line, col))
+    }
+
+    fn number(&mut self) -> Result<Number, Error> {
+        if self.flag()? {
+            Ok(Number::Float(f64::from_bits(self.u64()?)))
+        } else {
+            self.string()?.parse().map_err(|_| corrupt())
+        }
+    }
+
+    fn op(&mut self) -> Result<Op, Error> {
+        Ok(match self.u8()? {
+            0 => Op::Const(self.u32()?),
+            1 => Op::Bool(self.u32()? != 0),
+            2 => Op::LoadLocal(self.u32()?, self.u32()?),
+            3 => Op::LoadGlobal(self.u32()?),
+            4 => Op::StoreLocal(self.u32()?, self.u32()?),
+            5 => Op::StoreGlobal(self.u32()?),
+            6 => Op::CheckNum(self.operand()?),
+            7 => Op::CheckBool(self.operand()?),
+            8 => Op::Arith(*NUM_OPS.get(self.u32()? as usize).ok_or_else(corrupt)?, self.u32()?),
+            9 => Op::Not,
+            10 => Op::Jump(self.u32()?),
+            11 => Op::JumpIfFalse(self.u32()?),
+            12 => Op::JumpIfTrue(self.u32()?),
+            13 => Op::Closure(self.u32()?),
+            14 => Op::CheckCall(self.u32()?),
+            15 => Op::Call(self.u32()?),
+            16 => Op::TailCall(self.u32()?),
+            17 => Op::PrintNum,
+            18 => Op::PrintBool,
+            19 => Op::Return,
+            _ => return Err(corrupt()),
+        })
+    }
+
+    fn operand(&mut self) -> Result<Operand, Error> {
+        let operator = *Operator::ALL.get(self.u8()? as usize).ok_or_else(corrupt)?;
+        Ok(Operand::new(operator, self.u32()? as usize))
+    }
+
+    fn name(&mut self) -> Result<Option<String>, Error> {
+        Ok(if self.flag()? { Some(self.string()?) } else { None })
+    }
+
+    fn call_site(&mut self) -> Result<CallSite, Error> {
+        let name = self.name()?;
+        let argc = self.u32()?;
+        let span = self.span()?;
+        let func_span = self.span()?;
+        let check = if self.flag()? { Some((self.operand()?, self.span()?)) } else { None };
+        Ok(CallSite {
+            name,
+            argc,
+            span,
+            func_span,
+            check,
+        })
+    }
+
+    /// `scopes` holds the slot counts of the enclosing functions, innermost last.
+    /// Only functions have a frame; a top-level statement runs without one.
+    fn proto(&mut self, scopes: &mut Vec<usize>, function: bool, globals: usize) -> Result<Proto, Error> {
+        let name = self.name()?;
+        let arity = self.u32()?;
+        let slots: Rc<[String]> = self.seq(|decoder| decoder.string())?.into();
+        let code = self.seq(|decoder| decoder.op())?;
+        let spans = (0..code.len()).map(|_| self.span()).collect::<Result<_, _>>()?;
+        let consts = self.seq(|decoder| decoder.number())?;
+        if function {
+            scopes.push(slots.len());
+        }
+        let protos = self.seq(|decoder| decoder.proto(scopes, true, globals).map(Rc::new));
+        if function {
+            scopes.pop();
+        }
+        let proto = Proto {
+            name,
+            arity,
+            slots,
+            code,
+            spans,
+            consts,
+            protos: protos?,
+            calls: self.seq(|decoder| decoder.call_site())?,
+        };
+        validate(&proto, scopes, function, globals)?;
+        Ok(proto)
+    }
+}
+
+/// Checks that every operand refers to something that exists, so the VM cannot index out of bounds.
+fn validate(proto: &Proto, scopes: &[usize], function: bool, globals: usize) -> Result<(), Error> {
+    let len = proto.code.len() as u32;
+    // Slot counts of the frames a local access can reach, innermost first.
+    let frames: Vec<usize> = function
+        .then_some(proto.slots.len())
+        .into_iter()
+        .chain(scopes.iter().rev().copied())
+        .collect();
+    let valid = proto.code.iter().all(|op| match *op {
+        Op::Const(index) => (index as usize) < proto.consts.len(),
+        Op::LoadLocal(depth, slot) | Op::StoreLocal(depth, slot) => {
+            frames.get(depth as usize).is_some_and(|&count| (slot as usize) < count)
+        }
+        Op::LoadGlobal(slot) | Op::StoreGlobal(slot) => (slot as usize) < globals,
+        Op::Arith(_, argc) => argc > 0,
+        Op::Jump(target) | Op::JumpIfFalse(target) | Op::JumpIfTrue(target) => target < len,
+        Op::Closure(index) => (index as usize) < proto.protos.len(),
+        Op::CheckCall(site) | Op::Call(site) | Op::TailCall(site) => (site as usize) < proto.calls.len(),
+        _ => true,
+    });
+    // Execution must never run off the end of the code.
+    let ends = matches!(proto.code.last(), Some(Op::Return | Op::TailCall(_) | Op::Jump(_)));
+    if valid && ends && proto.arity as usize <= proto.slots.len() {
+        Ok(())
+    } else {
+        Err(corrupt())
+    }
+}
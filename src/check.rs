@@ -0,0 +1,96 @@
+use std::collections::HashMap;
+
+use crate::ast::*;
+use crate::error::Error;
+
+/// Arity of every name in a scope, or `None` when the name may hold something
+/// other than a function with a known parameter count.
+type Scope = HashMap<String, Option<usize>>;
+
+/// Reports calls whose argument count cannot match the called function,
+/// without running the program.
+pub fn check_arity(program: &Program) -> Vec<Error> {
+    let mut errors = Vec::new();
+    let mut scopes = vec![scope_of(&[], &program.stmts)];
+    for stmt in &program.stmts {
+        check_stmt(stmt, &mut scopes, &mut errors);
+    }
+    errors
+}
+
+/// A name only has a known arity if every definition of it in the scope binds
+/// a function literal with the same number of parameters.
+fn scope_of(params: &[Exp], stmts: &[Stmt]) -> Scope {
+    let mut scope = Scope::new();
+    for param in params {
+        scope.insert(param.to_string(), None);
+    }
+    for stmt in stmts {
+        if let StmtKind::DefStmt { id, exp } = &stmt.kind {
+            let arity = match &exp.kind {
+                ExpKind::FunExp(lambda) => Some(lambda.params.len()),
+                _ => None,
+            };
+            scope
+                .entry(id.to_string())
+                .and_modify(|known| {
+                    if *known != arity {
+                        *known = None
+                    }
+                })
+                .or_insert(arity);
+        }
+    }
+    scope
+}
+
+fn lookup(scopes: &[Scope], name: &str) -> Option<usize> {
+    scopes.iter().rev().find_map(|scope| scope.get(name)).copied().flatten()
+}
+
+fn check_stmt(stmt: &Stmt, scopes: &mut Vec<Scope>, errors: &mut Vec<Error>) {
+    match &stmt.kind {
+        StmtKind::ExpStmt { exp } | StmtKind::PrintStmt { exp, .. } | StmtKind::DefStmt { exp, .. } => {
+            check_exp(exp, scopes, errors)
+        }
+    }
+}
+
+fn check_exp(exp: &Exp, scopes: &mut Vec<Scope>, errors: &mut Vec<Error>) {
+    match &exp.kind {
+        ExpKind::Bool(_) | ExpKind::Num(_) | ExpKind::Id(_) | ExpKind::Var { .. } => {}
+        ExpKind::NumExp { args, .. } | ExpKind::LogicalExp { args, .. } => {
+            args.iter().for_each(|arg| check_exp(arg, scopes, errors));
+        }
+        ExpKind::IfExp { cond_exp, then_exp, else_exp } => {
+            check_exp(cond_exp, scopes, errors);
+            check_exp(then_exp, scopes, errors);
+            check_exp(else_exp, scopes, errors);
+        }
+        ExpKind::FunExp(lambda) => {
+            scopes.push(scope_of(&lambda.params, &lambda.def_stmts));
+            lambda.def_stmts.iter().for_each(|stmt| check_stmt(stmt, scopes, errors));
+            check_exp(&lambda.body, scopes, errors);
+            scopes.pop();
+        }
+        ExpKind::FunCall { func, args } => {
+            let (name, expected) = match &func.kind {
+                ExpKind::Id(name) | ExpKind::Var { name, .. } => (Some(name.clone()), lookup(scopes, name)),
+                ExpKind::FunExp(lambda) => (None, Some(lambda.params.len())),
+                _ => (None, None),
+            };
+            if let Some(expected) = expected {
+                if expected != args.len() {
+                    errors.push(Error::Arity {
+                        name,
+                        expected,
+                        found: args.len(),
+                        span: exp.span,
+                    });
+                }
+            }
+            check_exp(func, scopes, errors);
+            args.iter().for_each(|arg| check_exp(arg, scopes, errors));
+        }
+    }
+}
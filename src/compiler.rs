@@ -0,0 +1,293 @@
+use std::rc::Rc;
+
+use crate::ast::*;
+use crate::env::Env;
+use crate::number::Number;
+use crate::resolve;
+
+/// One VM instruction. Operands index into the tables of the owning `Proto`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    Const(u32),
+    Bool(bool),
+    /// Slot of the call frame `depth` functions out; depth 0 is the current frame.
+    LoadLocal(u32, u32),
+    LoadGlobal(u32),
+    StoreLocal(u32, u32),
+    StoreGlobal(u32),
+    /// Fails unless the value on top of the stack, given to the operand, is a number.
+    CheckNum(Operand),
+    /// Fails unless the value on top of the stack, given to the operand, is a boolean.
+    CheckBool(Operand),
+    /// Pops the given number of checked arguments and pushes the result.
+    Arith(NumOp, u32),
+    Not,
+    Jump(u32),
+    JumpIfFalse(u32),
+    JumpIfTrue(u32),
+    Closure(u32),
+    /// Fails unless the callee below the call site's arguments can take them.
+    /// Emitted before the arguments so errors come in the same order as the tree-walker's.
+    CheckCall(u32),
+    Call(u32),
+    /// Calls in place of the current frame.
+    TailCall(u32),
+    PrintNum,
+    PrintBool,
+    Return,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CallSite {
+    pub name: Option<String>,
+    pub argc: u32,
+    pub span: Span,
+    pub func_span: Span,
+    /// The `and`/`or` arm the callee's result must turn out a boolean for.
+    pub check: Option<(Operand, Span)>,
+}
+
+/// A compiled function, or a compiled top-level statement.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Proto {
+    /// The name of the function, as in `Lambda`.
+    pub name: Option<String>,
+    pub arity: u32,
+    /// Names of the call frame's slots, parameters first.
+    pub slots: Rc<[String]>,
+    pub code: Vec<Op>,
+    /// Source location of each instruction, for error messages.
+    pub spans: Vec<Span>,
+    pub consts: Vec<Number>,
+    pub protos: Vec<Rc<Proto>>,
+    pub calls: Vec<CallSite>,
+}
+
+/// A whole compiled program, ready to be written out as a `.lspc` file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Module {
+    /// The program text, kept so runtime errors can still quote it.
+    pub source: String,
+    /// Names of the global slots that `LoadGlobal` and `StoreGlobal` index.
+    pub globals: Vec<String>,
+    pub stmts: Vec<Rc<Proto>>,
+}
+
+pub fn compile_program(mut program: Program, source: &str) -> Module {
+    let mut globals = Env::new();
+    let stmts = program
+        .stmts
+        .iter_mut()
+        .map(|stmt| {
+            resolve::resolve_stmt(stmt, &mut globals);
+            Rc::new(compile_stmt(stmt))
+        })
+        .collect();
+    Module {
+        source: source.to_string(),
+        globals: globals.slot_names().to_vec(),
+        stmts,
+    }
+}
+
+/// Compiles a resolved top-level statement. Running it leaves the value of a bare
+/// expression statement on the stack and nothing otherwise.
+pub fn compile_stmt(stmt: &Stmt) -> Proto {
+    let mut compiler = Compiler::default();
+    compiler.stmt(stmt);
+    compiler.emit(Op::Return, stmt.span);
+    compiler.proto
+}
+
+fn compile_lambda(lambda: &Lambda) -> Proto {
+    let mut compiler = Compiler::default();
+    compiler.proto.name = lambda.name.clone();
+    compiler.proto.arity = lambda.params.len() as u32;
+    compiler.proto.slots = lambda.slots.clone();
+    for stmt in &lambda.def_stmts {
+        compiler.stmt(stmt);
+    }
+    compiler.tail(&lambda.body, None);
+    compiler.proto
+}
+
+#[derive(Default)]
+struct Compiler {
+    proto: Proto,
+}
+
+impl Compiler {
+    fn emit(&mut self, op: Op, span: Span) -> usize {
+        self.proto.code.push(op);
+        self.proto.spans.push(span);
+        self.proto.code.len() - 1
+    }
+
+    fn here(&self) -> u32 {
+        self.proto.code.len() as u32
+    }
+
+    /// Points the jump at `at` to the next instruction.
+    fn patch(&mut self, at: usize) {
+        let target = self.here();
+        match &mut self.proto.code[at] {
+            Op::Jump(to) | Op::JumpIfFalse(to) | Op::JumpIfTrue(to) => *to = target,
+            _ => unreachable!(),
+        }
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) {
+        match &stmt.kind {
+            StmtKind::ExpStmt { exp } => self.exp(exp),
+            StmtKind::DefStmt { id, exp } => {
+                self.exp(exp);
+                match id.kind {
+                    ExpKind::Var { addr: Addr::Local { depth, slot }, .. } => {
+                        self.emit(Op::StoreLocal(depth as u32, slot as u32), id.span)
+                    }
+                    ExpKind::Var { addr: Addr::Global(slot), .. } => self.emit(Op::StoreGlobal(slot as u32), id.span),
+                    _ => unreachable!("variables are resolved before compilation"),
+                };
+            }
+            StmtKind::PrintStmt { print_type, exp } => {
+                self.exp(exp);
+                match print_type {
+                    PrintType::PrintNum => self.emit(Op::PrintNum, exp.span),
+                    PrintType::PrintBool => self.emit(Op::PrintBool, exp.span),
+                };
+            }
+        }
+    }
+
+    /// Emits the arms of an `and`/`or` but the last, each jumping out as soon as it decides the result.
+    fn logical_arms(&mut self, op: LogicalOp, args: &[Box<Exp>]) -> Vec<usize> {
+        args.iter()
+            .enumerate()
+            .map(|(i, arg)| {
+                self.exp(arg);
+                self.emit(Op::CheckBool(Operand::new(Operator::Logical(op), i + 1)), arg.span);
+                match op {
+                    LogicalOp::Or => self.emit(Op::JumpIfTrue(0), arg.span),
+                    _ => self.emit(Op::JumpIfFalse(0), arg.span),
+                }
+            })
+            .collect()
+    }
+
+    fn call_site(&mut self, func: &Exp, args: &[Box<Exp>], span: Span, check: Option<(Operand, Span)>) -> u32 {
+        let name = match &func.kind {
+            ExpKind::Id(name) | ExpKind::Var { name, .. } => Some(name.clone()),
+            _ => None,
+        };
+        self.proto.calls.push(CallSite {
+            name,
+            argc: args.len() as u32,
+            span,
+            func_span: func.span,
+            check,
+        });
+        let site = self.proto.calls.len() as u32 - 1;
+        self.exp(func);
+        self.emit(Op::CheckCall(site), span);
+        for arg in args {
+            self.exp(arg);
+        }
+        site
+    }
+
+    /// Emits code leaving the value of `exp` on the stack.
+    fn exp(&mut self, exp: &Exp) {
+        let span = exp.span;
+        match &exp.kind {
+            ExpKind::Bool(val) => {
+                self.emit(Op::Bool(*val), span);
+            }
+            ExpKind::Num(val) => {
+                self.proto.consts.push(val.clone());
+                self.emit(Op::Const(self.proto.consts.len() as u32 - 1), span);
+            }
+            ExpKind::Id(_) => unreachable!("variables are resolved before compilation"),
+            ExpKind::Var { addr, .. } => {
+                match *addr {
+                    Addr::Local { depth, slot } => self.emit(Op::LoadLocal(depth as u32, slot as u32), span),
+                    Addr::Global(slot) => self.emit(Op::LoadGlobal(slot as u32), span),
+                };
+            }
+            ExpKind::NumExp { op, args } => {
+                for (i, arg) in args.iter().enumerate() {
+                    self.exp(arg);
+                    self.emit(Op::CheckNum(Operand::new(Operator::Num(*op), i + 1)), arg.span);
+                }
+                self.emit(Op::Arith(*op, args.len() as u32), span);
+            }
+            ExpKind::LogicalExp { op: LogicalOp::Not, args } => {
+                self.exp(&args[0]);
+                self.emit(Op::CheckBool(Operand::new(Operator::Logical(LogicalOp::Not), 1)), args[0].span);
+                self.emit(Op::Not, span);
+            }
+            ExpKind::LogicalExp { op, args } => {
+                let (last, args) = args.split_last().unwrap();
+                let jumps = self.logical_arms(*op, args);
+                self.exp(last);
+                self.emit(Op::CheckBool(Operand::new(Operator::Logical(*op), args.len() + 1)), last.span);
+                let end = self.emit(Op::Jump(0), span);
+                jumps.into_iter().for_each(|jump| self.patch(jump));
+                self.emit(Op::Bool(*op == LogicalOp::Or), span);
+                self.patch(end);
+            }
+            ExpKind::IfExp { cond_exp, then_exp, else_exp } => {
+                self.exp(cond_exp);
+                self.emit(Op::CheckBool(Operand::new(Operator::If, 1)), cond_exp.span);
+                let else_jump = self.emit(Op::JumpIfFalse(0), span);
+                self.exp(then_exp);
+                let end = self.emit(Op::Jump(0), span);
+                self.patch(else_jump);
+                self.exp(else_exp);
+                self.patch(end);
+            }
+            ExpKind::FunExp(lambda) => {
+                self.proto.protos.push(Rc::new(compile_lambda(lambda)));
+                self.emit(Op::Closure(self.proto.protos.len() as u32 - 1), span);
+            }
+            ExpKind::FunCall { func, args } => {
+                let site = self.call_site(func, args, span, None);
+                self.emit(Op::Call(site), span);
+            }
+        }
+    }
+
+    /// Emits code returning the value of `exp`, which is in tail position. `check` is
+    /// an enclosing `and`/`or` arm whose value must turn out to be a boolean.
+    fn tail(&mut self, exp: &Exp, check: Option<(Operand, Span)>) {
+        let span = exp.span;
+        match &exp.kind {
+            ExpKind::LogicalExp { op, args } if *op != LogicalOp::Not => {
+                let (last, args) = args.split_last().unwrap();
+                let jumps = self.logical_arms(*op, args);
+                self.tail(last, Some((Operand::new(Operator::Logical(*op), args.len() + 1), last.span)));
+                jumps.into_iter().for_each(|jump| self.patch(jump));
+                self.emit(Op::Bool(*op == LogicalOp::Or), span);
+                self.emit(Op::Return, span);
+            }
+            ExpKind::IfExp { cond_exp, then_exp, else_exp } => {
+                self.exp(cond_exp);
+                self.emit(Op::CheckBool(Operand::new(Operator::If, 1)), cond_exp.span);
+                let else_jump = self.emit(Op::JumpIfFalse(0), span);
+                self.tail(then_exp, check);
+                self.patch(else_jump);
+                self.tail(else_exp, check);
+            }
+            ExpKind::FunCall { func, args } => {
+                let site = self.call_site(func, args, span, check);
+                self.emit(Op::TailCall(site), span);
+            }
+            _ => {
+                self.exp(exp);
+                if let Some((operand, span)) = check {
+                    self.emit(Op::CheckBool(operand), span);
+                }
+                self.emit(Op::Return, span);
+            }
+        }
+    }
+}
@@ -0,0 +1,176 @@
+use std::io::IsTerminal;
+
+use colored::Colorize;
+
+use crate::ast::Span;
+use crate::error::Error;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColorChoice {
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    pub fn parse(choice: &str) -> Option<Self> {
+        match choice {
+            "auto" => Some(ColorChoice::Auto),
+            "always" => Some(ColorChoice::Always),
+            "never" => Some(ColorChoice::Never),
+            _ => None,
+        }
+    }
+
+    pub fn apply(self) {
+        match self {
+            ColorChoice::Always => colored::control::set_override(true),
+            ColorChoice::Never => colored::control::set_override(false),
+            ColorChoice::Auto => {
+                // `colored` already honors NO_COLOR and CLICOLOR, but checks stdout rather than stderr.
+                if std::env::var_os("NO_COLOR").is_some() || !std::io::stderr().is_terminal() {
+                    colored::control::set_override(false);
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub title: String,
+    pub message: String,
+    pub span: Option<Span>,
+    pub label: Option<String>,
+    pub notes: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn new(title: &str, message: String) -> Self {
+        Diagnostic {
+            title: title.to_string(),
+            message,
+            span: None,
+            label: None,
+            notes: Vec::new(),
+        }
+    }
+
+    pub fn from_error(err: &Error) -> Self {
+        let mut diagnostic = Diagnostic::new(err.title(), err.message());
+        diagnostic.span = err.span();
+        match err {
+            Error::Syntax { message, .. } => {
+                diagnostic.label = Some(message.clone());
+            }
+            Error::Type { expected, found, .. } => {
+                diagnostic.label = Some(format!("this is a {}, not a {}", found, expected));
+            }
+            Error::TypeMismatch { found, .. } => {
+                diagnostic.label = Some(format!("this has type {}", found));
+            }
+            Error::InfiniteType { .. } => {
+                diagnostic.label = Some("this would have to contain its own type".to_string());
+            }
+            Error::UnboundVariable { suggestion, .. } => {
+                diagnostic.label = Some("not found in this scope".to_string());
+                if let Some(suggestion) = suggestion {
+                    diagnostic.notes.push(format!("help: did you mean `{}`?", suggestion));
+                }
+            }
+            Error::Arity { expected, .. } => {
+                diagnostic.label = Some(format!("expected {} argument{}", expected, if *expected == 1 { "" } else { "s" }));
+            }
+            Error::DivisionByZero { .. } => {
+                diagnostic.label = Some("divisor is zero".to_string());
+            }
+            Error::NotCallable { found, .. } => {
+                diagnostic.label = Some(format!("this is a {}, not a function", found));
+            }
+            Error::Overflow { .. } => {
+                diagnostic.label = Some("result does not fit in a 64-bit integer".to_string());
+            }
+            Error::NoExactValue { .. } => {
+                diagnostic.label = Some("this is infinite or not a number".to_string());
+            }
+            Error::RecursionLimit { limit, backtrace, .. } => {
+                diagnostic.label = Some("this call is one too many".to_string());
+                for frame in backtrace {
+                    diagnostic.notes.push(format!(
+                        "note: inside {} called at {}",
+                        frame.name.as_ref().map_or("anonymous function".to_string(), |name| format!("'{}'", name)),
+                        frame.span
+                    ));
+                }
+                if *limit > backtrace.len() {
+                    diagnostic.notes.push(format!("note: ... and {} more", limit - backtrace.len()));
+                }
+            }
+            Error::Unsupported { target, .. } => {
+                diagnostic.label = Some(format!("not available when compiling to {}", target));
+            }
+            Error::Bytecode { .. } | Error::Io(_) => {}
+        }
+        diagnostic
+    }
+
+    pub fn render(&self, file: &str, source: &str) -> String {
+        let mut out = format!("{}: {}\n", self.title.red().bold(), self.message.bold());
+        let Some(span) = self.span else {
+            for note in &self.notes {
+                out.push_str(&format!("  {} {}\n", "=".blue().bold(), note));
+            }
+            return out;
+        };
+
+        let gutter = " ".repeat(span.line.to_string().len());
+        let bar = "|".blue().bold();
+        out.push_str(&format!("{}{} {}:{}\n", gutter, "-->".blue().bold(), file, span));
+
+        if let (Some(text), Some(rest)) = (source.lines().nth(span.line - 1), source.get(span.start..)) {
+            let line_end = span.start + rest.find('\n').unwrap_or(rest.len());
+            let width = source[span.start..span.end.clamp(span.start, line_end)].chars().count().max(1);
+            let underline = format!("{}{}", " ".repeat(span.col - 1), "^".repeat(width));
+
+            out.push_str(&format!("{} {}\n", gutter, bar));
+            out.push_str(&format!("{} {} {}\n", span.line.to_string().blue().bold(), bar, text));
+            out.push_str(&format!("{} {} {}", gutter, bar, underline.red().bold()));
+            if let Some(label) = &self.label {
+                out.push_str(&format!(" {}", label.red().bold()));
+            }
+            out.push('\n');
+        }
+        if !self.notes.is_empty() {
+            out.push_str(&format!("{} {}\n", gutter, bar));
+        }
+        for note in &self.notes {
+            out.push_str(&format!("{} {} {}\n", gutter, "=".blue().bold(), note));
+        }
+        out
+    }
+}
+
+pub fn suggest<'a, I: IntoIterator<Item = &'a str>>(name: &str, candidates: I) -> Option<String> {
+    let threshold = (name.chars().count() / 3).max(1);
+    candidates
+        .into_iter()
+        .filter(|candidate| *candidate != name)
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        .filter(|(distance, _)| *distance <= threshold)
+        .min()
+        .map(|(_, candidate)| candidate.to_string())
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut curr = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let cost = if ca == *cb { 0 } else { 1 };
+            curr[j + 1] = (prev[j] + cost).min(prev[j + 1] + 1).min(curr[j] + 1);
+        }
+        prev = curr;
+    }
+    prev[b.len()]
+}
@@ -0,0 +1,579 @@
+//! Translates a program into a single C file that builds with any C99 compiler
+//! supporting `__builtin_*_overflow` (gcc and clang).
+//!
+//! Values are tagged structs, every function becomes a C function taking its call
+//! frame, and closures pair that function with the frame they were created in.
+//! Tail calls go through a trampoline in `call`, so tail recursion runs in constant
+//! stack and memory. A frame is freed when its call returns unless a closure captured
+//! it, and closures are never freed. Only fixnums are supported: rational, inexact and
+//! big numbers are rejected at compile time when they appear as literals and at
+//! run time when arithmetic produces them.
+
+use std::fmt::Write;
+
+use crate::ast::*;
+use crate::env::Env;
+use crate::error::Error;
+use crate::interpreter::Options;
+use crate::number::{Number, OverflowMode};
+use crate::optimize;
+use crate::resolve;
+
+const RUNTIME: &str = r#"#include <stdarg.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+
+typedef struct Frame Frame;
+typedef struct Closure Closure;
+
+typedef enum { UNBOUND, NUM, BOOL, FUN, TAIL } Tag;
+
+typedef struct {
+    Tag tag;
+    union {
+        int64_t num;
+        int boolean;
+        Closure *fun;
+    } as;
+} Value;
+
+struct Frame {
+    Frame *outer;
+    /* Set once a closure may refer to the frame, which then lives as long as the program. */
+    int captured;
+    Value slots[];
+};
+
+struct Closure {
+    Value (*code)(Frame *frame);
+    int arity;
+    int slots;
+    Frame *env;
+};
+
+static Value globals[GLOBALS + 1];
+static int depth;
+/* The call a function made in tail position, for `call` to run next. */
+static struct {
+    Closure *fun;
+    Frame *frame;
+    int line, col;
+    const char *operand;
+} pending;
+
+static void fail(int line, int col, const char *title, const char *format, ...) {
+    va_list args;
+    va_start(args, format);
+    fflush(stdout);
+    fprintf(stderr, "%s: ", title);
+    vfprintf(stderr, format, args);
+    fprintf(stderr, "\n --> %s:%d:%d\n", SOURCE, line, col);
+    va_end(args);
+    exit(1);
+}
+
+static void *alloc(size_t size) {
+    void *ptr = calloc(1, size);
+    if (!ptr) {
+        fputs("out of memory\n", stderr);
+        exit(1);
+    }
+    return ptr;
+}
+
+static const char *type_name(Value val) {
+    switch (val.tag) {
+    case NUM:
+        return "number";
+    case BOOL:
+        return "boolean";
+    default:
+        return "function";
+    }
+}
+
+static Value num_val(int64_t num) {
+    return (Value){ .tag = NUM, .as.num = num };
+}
+
+static Value bool_val(int boolean) {
+    return (Value){ .tag = BOOL, .as.boolean = boolean };
+}
+
+static int64_t as_num(Value val, int line, int col, const char *operand) {
+    if (val.tag != NUM) {
+        fail(line, col, "type error", "%s: expected number, got %s", operand, type_name(val));
+    }
+    return val.as.num;
+}
+
+static int as_bool(Value val, int line, int col, const char *operand) {
+    if (val.tag != BOOL) {
+        fail(line, col, "type error", "%s: expected boolean, got %s", operand, type_name(val));
+    }
+    return val.as.boolean;
+}
+
+static Value load(Value val, const char *name, int line, int col) {
+    if (val.tag == UNBOUND) {
+        fail(line, col, "unbound variable", "variable '%s' not found", name);
+    }
+    return val;
+}
+
+static int64_t overflow(uint64_t wrapped, int line, int col) {
+    if (OVERFLOW_WRAP) {
+        return (int64_t)wrapped;
+    }
+    if (OVERFLOW_ERROR) {
+        fail(line, col, "arithmetic error", "integer overflow");
+    }
+    fail(line, col, "unsupported", "big numbers cannot be compiled to C");
+    return 0;
+}
+
+static int64_t add(int64_t a, int64_t b, int line, int col) {
+    int64_t val;
+    return __builtin_add_overflow(a, b, &val) ? overflow((uint64_t)a + (uint64_t)b, line, col) : val;
+}
+
+static int64_t sub(int64_t a, int64_t b, int line, int col) {
+    int64_t val;
+    return __builtin_sub_overflow(a, b, &val) ? overflow((uint64_t)a - (uint64_t)b, line, col) : val;
+}
+
+static int64_t mul(int64_t a, int64_t b, int line, int col) {
+    int64_t val;
+    return __builtin_mul_overflow(a, b, &val) ? overflow((uint64_t)a * (uint64_t)b, line, col) : val;
+}
+
+static int64_t quo(int64_t a, int64_t b, int line, int col) {
+    if (b == 0) {
+        fail(line, col, "arithmetic error", "division by zero");
+    }
+    if (b == -1) {
+        return sub(0, a, line, col);
+    }
+    if (a % b != 0) {
+        fail(line, col, "unsupported", "rational numbers cannot be compiled to C");
+    }
+    return a / b;
+}
+
+static int64_t rem(int64_t a, int64_t b, int line, int col) {
+    if (b == 0) {
+        fail(line, col, "arithmetic error", "division by zero");
+    }
+    if (b == -1) {
+        if (a == INT64_MIN && OVERFLOW_ERROR) {
+            fail(line, col, "arithmetic error", "integer overflow");
+        }
+        return 0;
+    }
+    return a % b;
+}
+
+static Value closure(Value (*code)(Frame *frame), int arity, int slots, Frame *env) {
+    Closure *fun = alloc(sizeof(Closure));
+    fun->code = code;
+    fun->arity = arity;
+    fun->slots = slots;
+    fun->env = env;
+    for (Frame *frame = env; frame && !frame->captured; frame = frame->outer) {
+        frame->captured = 1;
+    }
+    return (Value){ .tag = FUN, .as.fun = fun };
+}
+
+static void release(Frame *frame) {
+    if (!frame->captured) {
+        free(frame);
+    }
+}
+
+/* Checks that `val` can take `argc` arguments and makes the frame they go in. */
+static Frame *enter(Value val, int argc, const char *name, int line, int col, int func_line, int func_col) {
+    if (val.tag != FUN) {
+        fail(func_line, func_col, "type error", "'%s' is not callable", type_name(val));
+    }
+    Closure *fun = val.as.fun;
+    if (fun->arity != argc) {
+        fail(line, col, "arity error", "%s%s%s expects %d argument%s but got %d", name ? "function '" : "anonymous function",
+             name ? name : "", name ? "'" : "", fun->arity, fun->arity == 1 ? "" : "s", argc);
+    }
+    Frame *frame = alloc(sizeof(Frame) + fun->slots * sizeof(Value));
+    frame->outer = fun->env;
+    return frame;
+}
+
+/* `line` is nonzero when the callee's result must turn out a boolean. */
+static Value tail_call(Value val, Frame *frame, int line, int col, const char *operand) {
+    pending.fun = val.as.fun;
+    pending.frame = frame;
+    pending.line = line;
+    pending.col = col;
+    pending.operand = operand;
+    return (Value){ .tag = TAIL };
+}
+
+static Value call(Value val, Frame *frame, int line, int col) {
+    if (depth >= MAX_DEPTH) {
+        fail(line, col, "recursion error", "maximum recursion depth of %d exceeded", MAX_DEPTH);
+    }
+    depth++;
+    int check_line = 0, check_col = 0;
+    const char *check_operand = NULL;
+    Value result = val.as.fun->code(frame);
+    while (result.tag == TAIL) {
+        if (pending.line) {
+            check_line = pending.line;
+            check_col = pending.col;
+            check_operand = pending.operand;
+        }
+        /* The arguments of the tail call are already in its own frame. */
+        release(frame);
+        frame = pending.frame;
+        result = pending.fun->code(frame);
+    }
+    release(frame);
+    depth--;
+    if (check_line) {
+        as_bool(result, check_line, check_col, check_operand);
+    }
+    return result;
+}
+"#;
+
+pub fn emit(mut program: Program, file: &str, options: &Options) -> Result<String, Error> {
+    if options.optimize {
+        optimize::optimize(&mut program, options.overflow);
+    }
+    let mut globals = Env::new();
+    resolve::resolve(&mut program, &mut globals);
+
+    let mut emitter = Emitter {
+        indent: 1,
+        ..Default::default()
+    };
+    for stmt in &program.stmts {
+        emitter.stmt(stmt)?;
+    }
+
+    let mut out = String::new();
+    writeln!(out, "#define SOURCE {:?}", file).unwrap();
+    writeln!(out, "#define GLOBALS {}", globals.slot_names().len()).unwrap();
+    writeln!(out, "#define MAX_DEPTH {}", options.max_depth).unwrap();
+    writeln!(out, "#define OVERFLOW_WRAP {}", (options.overflow == OverflowMode::Wrap) as u8).unwrap();
+    writeln!(out, "#define OVERFLOW_ERROR {}", (options.overflow == OverflowMode::Error) as u8).unwrap();
+    out.push('\n');
+    out.push_str(RUNTIME);
+    out.push('\n');
+    for index in 0..emitter.funs.len() {
+        writeln!(out, "static Value fun_{}(Frame *frame);", index).unwrap();
+    }
+    for fun in &emitter.funs {
+        write!(out, "\n{}", fun).unwrap();
+    }
+    write!(out, "\nint main(void) {{\n    Frame *frame = NULL;\n    (void)frame;\n{}    return 0;\n}}\n", emitter.body).unwrap();
+    Ok(out)
+}
+
+fn unsupported(what: &'static str, span: Span) -> Error {
+    Error::Unsupported { what, target: "C", span }
+}
+
+#[derive(Default)]
+struct Emitter {
+    /// Definitions of the functions finished so far, numbered by position.
+    funs: Vec<String>,
+    /// Body of the C function being emitted.
+    body: String,
+    temps: usize,
+    indent: usize,
+}
+
+impl Emitter {
+    fn line(&mut self, line: &str) {
+        self.body.push_str(&"    ".repeat(self.indent));
+        self.body.push_str(line);
+        self.body.push('\n');
+    }
+
+    fn temp(&mut self) -> String {
+        self.temps += 1;
+        format!("v{}", self.temps)
+    }
+
+    /// Emits `init` into a fresh `Value` variable and returns its name.
+    fn bind(&mut self, init: &str) -> String {
+        let temp = self.temp();
+        self.line(&format!("Value {} = {};", temp, init));
+        temp
+    }
+
+    /// Emits an `if` on the boolean `cond`, with `then` and `otherwise` emitting the branches.
+    fn branch(
+        &mut self,
+        cond: &str,
+        span: Span,
+        then: impl FnOnce(&mut Self) -> Result<(), Error>,
+        otherwise: impl FnOnce(&mut Self) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        self.line(&format!("if ({}) {{", convert("as_bool", cond, Operand::new(Operator::If, 1), span)));
+        self.indent += 1;
+        then(self)?;
+        self.indent -= 1;
+        self.line("} else {");
+        self.indent += 1;
+        otherwise(self)?;
+        self.indent -= 1;
+        self.line("}");
+        Ok(())
+    }
+
+    fn assign(&mut self, temp: &str, exp: &Exp) -> Result<(), Error> {
+        let val = self.exp(exp)?;
+        self.line(&format!("{} = {};", temp, val));
+        Ok(())
+    }
+
+    fn store(&mut self, id: &Exp, val: &str) {
+        let place = match id.kind {
+            ExpKind::Var { addr, .. } => place(addr),
+            _ => unreachable!("variables are resolved before emitting"),
+        };
+        self.line(&format!("{} = {};", place, val));
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) -> Result<(), Error> {
+        match &stmt.kind {
+            StmtKind::ExpStmt { exp } => {
+                let val = self.exp(exp)?;
+                self.line(&format!("(void){};", val));
+            }
+            StmtKind::DefStmt { id, exp } => {
+                let val = self.exp(exp)?;
+                self.store(id, &val);
+            }
+            StmtKind::PrintStmt { print_type, exp } => {
+                let val = self.exp(exp)?;
+                let operand = Operand::new(Operator::Print(*print_type), 1);
+                match print_type {
+                    PrintType::PrintNum => {
+                        let num = convert("as_num", &val, operand, exp.span);
+                        self.line(&format!("printf(\"%lld\\n\", (long long){});", num))
+                    }
+                    PrintType::PrintBool => {
+                        let val = convert("as_bool", &val, operand, exp.span);
+                        self.line(&format!("puts({} ? \"#t\" : \"#f\");", val))
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn lambda(&mut self, lambda: &Lambda) -> Result<usize, Error> {
+        let outer_body = std::mem::take(&mut self.body);
+        let outer_temps = std::mem::replace(&mut self.temps, 0);
+        let outer_indent = std::mem::replace(&mut self.indent, 1);
+        let result = lambda
+            .def_stmts
+            .iter()
+            .try_for_each(|stmt| self.stmt(stmt))
+            .and_then(|_| self.tail(&lambda.body, None));
+        let body = std::mem::replace(&mut self.body, outer_body);
+        self.temps = outer_temps;
+        self.indent = outer_indent;
+        result?;
+        // Nested functions were pushed first, so this one takes the next free number.
+        self.funs.push(format!("static Value fun_{}(Frame *frame) {{\n{}}}\n", self.funs.len(), body));
+        Ok(self.funs.len() - 1)
+    }
+
+    /// Emits the callee and arguments of a call, returning the callee and its new frame.
+    fn call_site(&mut self, func: &Exp, args: &[Box<Exp>], span: Span) -> Result<(String, String), Error> {
+        let name = match &func.kind {
+            ExpKind::Var { name, .. } => format!("{:?}", name),
+            _ => "NULL".to_string(),
+        };
+        let callee = self.exp(func)?;
+        let frame = self.temp();
+        self.line(&format!(
+            "Frame *{} = enter({}, {}, {}, {}, {}, {}, {});",
+            frame,
+            callee,
+            args.len(),
+            name,
+            span.line,
+            span.col,
+            func.span.line,
+            func.span.col
+        ));
+        for (slot, arg) in args.iter().enumerate() {
+            let val = self.exp(arg)?;
+            self.line(&format!("{}->slots[{}] = {};", frame, slot, val));
+        }
+        Ok((callee, frame))
+    }
+
+    /// Emits the arms of an `and`/`or` but the last. Each either jumps out of the
+    /// enclosing loop as soon as it decides the result, or returns it when `tail` is set.
+    fn logical_arms(&mut self, op: LogicalOp, args: &[Box<Exp>], tail: bool) -> Result<(), Error> {
+        for (i, arg) in args.iter().enumerate() {
+            let val = self.exp(arg)?;
+            let test = convert("as_bool", &val, Operand::new(Operator::Logical(op), i + 1), arg.span);
+            let exit = if tail {
+                format!("return bool_val({});", (op == LogicalOp::Or) as u8)
+            } else {
+                "break;".to_string()
+            };
+            match op {
+                LogicalOp::Or => self.line(&format!("if ({}) {}", test, exit)),
+                _ => self.line(&format!("if (!{}) {}", test, exit)),
+            }
+        }
+        Ok(())
+    }
+
+    /// Emits code computing the value of `exp` and returns a C expression for it.
+    fn exp(&mut self, exp: &Exp) -> Result<String, Error> {
+        let Span { line, col, .. } = exp.span;
+        Ok(match &exp.kind {
+            ExpKind::Bool(val) => format!("bool_val({})", *val as u8),
+            ExpKind::Num(Number::Int(val)) if *val == i64::MIN => "num_val(INT64_MIN)".to_string(),
+            ExpKind::Num(Number::Int(val)) => format!("num_val({})", val),
+            ExpKind::Num(Number::Big(_)) => return Err(unsupported("big numbers", exp.span)),
+            ExpKind::Num(Number::Ratio(_)) => return Err(unsupported("rational numbers", exp.span)),
+            ExpKind::Num(Number::Float(_)) => return Err(unsupported("inexact numbers", exp.span)),
+            ExpKind::Id(_) => unreachable!("variables are resolved before emitting"),
+            ExpKind::Var { name, addr } => self.bind(&format!("load({}, {:?}, {}, {})", place(*addr), name, line, col)),
+            ExpKind::NumExp { op, args } => {
+                if *op == NumOp::ToInexact {
+                    return Err(unsupported("inexact numbers", exp.span));
+                }
+                let mut nums = Vec::new();
+                for (i, arg) in args.iter().enumerate() {
+                    let val = self.exp(arg)?;
+                    let num = self.temp();
+                    let operand = Operand::new(Operator::Num(*op), i + 1);
+                    self.line(&format!("int64_t {} = {};", num, convert("as_num", &val, operand, arg.span)));
+                    nums.push(num);
+                }
+                let fold = |fun: &str| {
+                    nums[1..]
+                        .iter()
+                        .fold(nums[0].clone(), |acc, num| format!("{}({}, {}, {}, {})", fun, acc, num, line, col))
+                };
+                let val = match op {
+                    NumOp::Plus => format!("num_val({})", fold("add")),
+                    NumOp::Minus => format!("num_val({})", fold("sub")),
+                    NumOp::Multiply => format!("num_val({})", fold("mul")),
+                    NumOp::Divide => format!("num_val({})", fold("quo")),
+                    NumOp::Modulus => format!("num_val({})", fold("rem")),
+                    NumOp::Greater => format!("bool_val({} > {})", nums[0], nums[1]),
+                    NumOp::Smaller => format!("bool_val({} < {})", nums[0], nums[1]),
+                    NumOp::Equal => {
+                        let pairs: Vec<String> = nums.windows(2).map(|pair| format!("{} == {}", pair[0], pair[1])).collect();
+                        format!("bool_val({})", pairs.join(" && "))
+                    }
+                    NumOp::ToExact => format!("num_val({})", nums[0]),
+                    NumOp::ToInexact => unreachable!(),
+                };
+                self.bind(&val)
+            }
+            ExpKind::LogicalExp { op: LogicalOp::Not, args } => {
+                let val = self.exp(&args[0])?;
+                let operand = Operand::new(Operator::Logical(LogicalOp::Not), 1);
+                self.bind(&format!("bool_val(!{})", convert("as_bool", &val, operand, args[0].span)))
+            }
+            ExpKind::LogicalExp { op, args } => {
+                let result = self.bind(&format!("bool_val({})", (*op == LogicalOp::Or) as u8));
+                let (last, args) = args.split_last().unwrap();
+                // A loop that runs once, so the arms can `break` out of it.
+                self.line("do {");
+                self.indent += 1;
+                self.logical_arms(*op, args, false)?;
+                let val = self.exp(last)?;
+                let operand = Operand::new(Operator::Logical(*op), args.len() + 1);
+                self.line(&format!("{} = bool_val({});", result, convert("as_bool", &val, operand, last.span)));
+                self.indent -= 1;
+                self.line("} while (0);");
+                result
+            }
+            ExpKind::IfExp { cond_exp, then_exp, else_exp } => {
+                let result = self.temp();
+                self.line(&format!("Value {};", result));
+                let cond = self.exp(cond_exp)?;
+                self.branch(
+                    &cond,
+                    cond_exp.span,
+                    |emitter| emitter.assign(&result, then_exp),
+                    |emitter| emitter.assign(&result, else_exp),
+                )?;
+                result
+            }
+            ExpKind::FunExp(lambda) => {
+                let index = self.lambda(lambda)?;
+                let init = format!("closure(fun_{}, {}, {}, frame)", index, lambda.params.len(), lambda.slots.len());
+                self.bind(&init)
+            }
+            ExpKind::FunCall { func, args } => {
+                let (callee, frame) = self.call_site(func, args, exp.span)?;
+                self.bind(&format!("call({}, {}, {}, {})", callee, frame, line, col))
+            }
+        })
+    }
+
+    /// Emits code returning the value of `exp`, which is in tail position. `check` is
+    /// an enclosing `and`/`or` arm whose value must turn out to be a boolean.
+    fn tail(&mut self, exp: &Exp, check: Option<(Operand, Span)>) -> Result<(), Error> {
+        match &exp.kind {
+            ExpKind::LogicalExp { op, args } if *op != LogicalOp::Not => {
+                let (last, args) = args.split_last().unwrap();
+                self.logical_arms(*op, args, true)?;
+                self.tail(last, Some((Operand::new(Operator::Logical(*op), args.len() + 1), last.span)))
+            }
+            ExpKind::IfExp { cond_exp, then_exp, else_exp } => {
+                let cond = self.exp(cond_exp)?;
+                self.branch(
+                    &cond,
+                    cond_exp.span,
+                    |emitter| emitter.tail(then_exp, check),
+                    |emitter| emitter.tail(else_exp, check),
+                )
+            }
+            ExpKind::FunCall { func, args } => {
+                let (callee, frame) = self.call_site(func, args, exp.span)?;
+                let (operand, span) = match check {
+                    Some((operand, span)) => (format!("\"{}\"", operand), span),
+                    None => ("NULL".to_string(), Span::default()),
+                };
+                self.line(&format!("return tail_call({}, {}, {}, {}, {});", callee, frame, span.line, span.col, operand));
+                Ok(())
+            }
+            _ => {
+                let val = self.exp(exp)?;
+                if let Some((operand, span)) = check {
+                    self.line(&format!("{};", convert("as_bool", &val, operand, span)));
+                }
+                self.line(&format!("return {};", val));
+                Ok(())
+            }
+        }
+    }
+}
+
+/// A call of the runtime's `as_num` or `as_bool` converting `val` for `operand`.
+fn convert(func: &str, val: &str, operand: Operand, span: Span) -> String {
+    format!("{}({}, {}, {}, \"{}\")", func, val, span.line, span.col, operand)
+}
+
+/// The C lvalue holding the variable at `addr`.
+fn place(addr: Addr) -> String {
+    match addr {
+        Addr::Local { depth, slot } => format!("frame{}->slots[{}]", "->outer".repeat(depth), slot),
+        Addr::Global(slot) => format!("globals[{}]", slot),
+    }
+}
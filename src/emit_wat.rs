@@ -0,0 +1,709 @@
+//! Translates a program into a WebAssembly text module.
+//!
+//! Every value is a pointer to a box in linear memory: a tag word (1 number, 2 boolean,
+//! 3 function) followed by the payload. Null stands for a variable without a value yet.
+//! Functions are entries of a `funcref` table taking their call frame, closures pair a
+//! table index with the frame they were created in, and tail calls go through a
+//! trampoline in `$call`. Memory is never freed. Only fixnums are supported, as in
+//! [`emit_c`](crate::emit_c).
+//!
+//! The module exports `memory` and a `main` function running the program, and imports:
+//!
+//! - `env.print_num (i64)` and `env.print_bool (i32)`,
+//! - `env.fail (code span a b c)`, which reports a runtime error and must trap.
+//!   [`runtime_error`] decodes its arguments.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use crate::ast::*;
+use crate::env::Env;
+use crate::error::Error;
+use crate::interpreter::Options;
+use crate::number::{Number, OverflowMode};
+use crate::optimize;
+use crate::resolve;
+
+const FALSE: u32 = 16;
+const TRUE: u32 = 32;
+/// Globals come first after the statically allocated values.
+const GLOBALS_BASE: u32 = 64;
+
+const RUNTIME: &str = r#"(module
+  (import "env" "print_num" (func $print_num (param i64)))
+  (import "env" "print_bool" (func $print_bool (param i32)))
+  (import "env" "fail" (func $fail (param i32 i32 i32 i32 i32)))
+
+  (type $code (func (param i32) (result i32)))
+
+  (memory (export "memory") 1)
+  ;; #f at 16 and #t at 32. Returning 48 means a tail call is pending.
+  (data (i32.const 16) "\02\00\00\00\00\00\00\00\00\00\00\00\00\00\00\00\02\00\00\00\00\00\00\00\01\00\00\00\00\00\00\00")
+
+  (global $depth (mut i32) (i32.const 0))
+  (global $pending_fun (mut i32) (i32.const 0))
+  (global $pending_frame (mut i32) (i32.const 0))
+  (global $pending_check (mut i32) (i32.const 0))
+  (global $pending_operand (mut i32) (i32.const 0))
+
+  (func $alloc (param $size i32) (result i32)
+    (local $ptr i32)
+    (local.set $ptr (global.get $heap))
+    (global.set $heap (i32.add (local.get $ptr) (i32.and (i32.add (local.get $size) (i32.const 7)) (i32.const -8))))
+    (if (i32.gt_u (global.get $heap) (i32.shl (memory.size) (i32.const 16)))
+      (then
+        (if (i32.eq
+              (memory.grow (i32.add (i32.shr_u (i32.sub (global.get $heap) (i32.shl (memory.size) (i32.const 16))) (i32.const 16)) (i32.const 1)))
+              (i32.const -1))
+          (then (unreachable)))))
+    (local.get $ptr))
+
+  (func $num (param $val i64) (result i32)
+    (local $ptr i32)
+    (local.set $ptr (call $alloc (i32.const 16)))
+    (i32.store (local.get $ptr) (i32.const 1))
+    (i64.store offset=8 (local.get $ptr) (local.get $val))
+    (local.get $ptr))
+
+  (func $bool (param $val i32) (result i32)
+    (select (i32.const 32) (i32.const 16) (local.get $val)))
+
+  (func $as_num (param $val i32) (param $span i32) (param $operand i32) (result i64)
+    (if (i32.ne (i32.load (local.get $val)) (i32.const 1))
+      (then
+        (call $fail (i32.const 0) (local.get $span) (i32.const 1) (i32.load (local.get $val)) (local.get $operand))
+        (unreachable)))
+    (i64.load offset=8 (local.get $val)))
+
+  (func $as_bool (param $val i32) (param $span i32) (param $operand i32) (result i32)
+    (if (i32.ne (i32.load (local.get $val)) (i32.const 2))
+      (then
+        (call $fail (i32.const 0) (local.get $span) (i32.const 2) (i32.load (local.get $val)) (local.get $operand))
+        (unreachable)))
+    (i32.load offset=8 (local.get $val)))
+
+  (func $load (param $val i32) (param $name i32) (param $span i32) (result i32)
+    (if (i32.eqz (local.get $val))
+      (then
+        (call $fail (i32.const 2) (local.get $span) (local.get $name) (i32.const 0) (i32.const 0))
+        (unreachable)))
+    (local.get $val))
+
+  (func $overflow (param $wrapped i64) (param $span i32) (result i64)
+    (if (i32.eqz (global.get $overflow))
+      (then (return (local.get $wrapped))))
+    (call $fail
+      (select (i32.const 5) (i32.const 7) (i32.eq (global.get $overflow) (i32.const 1)))
+      (local.get $span) (i32.const 0) (i32.const 0) (i32.const 0))
+    (unreachable))
+
+  (func $add (param $a i64) (param $b i64) (param $span i32) (result i64)
+    (local $val i64)
+    (local.set $val (i64.add (local.get $a) (local.get $b)))
+    (if (i64.lt_s (i64.and (i64.xor (local.get $a) (local.get $val)) (i64.xor (local.get $b) (local.get $val))) (i64.const 0))
+      (then (return (call $overflow (local.get $val) (local.get $span)))))
+    (local.get $val))
+
+  (func $sub (param $a i64) (param $b i64) (param $span i32) (result i64)
+    (local $val i64)
+    (local.set $val (i64.sub (local.get $a) (local.get $b)))
+    (if (i64.lt_s (i64.and (i64.xor (local.get $a) (local.get $b)) (i64.xor (local.get $a) (local.get $val))) (i64.const 0))
+      (then (return (call $overflow (local.get $val) (local.get $span)))))
+    (local.get $val))
+
+  (func $mul (param $a i64) (param $b i64) (param $span i32) (result i64)
+    (local $val i64)
+    (local.set $val (i64.mul (local.get $a) (local.get $b)))
+    (if (if (result i32) (i64.eq (local.get $a) (i64.const -1))
+          (then (i64.eq (local.get $b) (i64.const 0x8000000000000000)))
+          (else (if (result i32) (i64.eqz (local.get $a))
+            (then (i32.const 0))
+            (else (i64.ne (i64.div_s (local.get $val) (local.get $a)) (local.get $b))))))
+      (then (return (call $overflow (local.get $val) (local.get $span)))))
+    (local.get $val))
+
+  (func $quo (param $a i64) (param $b i64) (param $span i32) (result i64)
+    (if (i64.eqz (local.get $b))
+      (then
+        (call $fail (i32.const 4) (local.get $span) (i32.const 0) (i32.const 0) (i32.const 0))
+        (unreachable)))
+    (if (i64.eq (local.get $b) (i64.const -1))
+      (then (return (call $sub (i64.const 0) (local.get $a) (local.get $span)))))
+    (if (i64.ne (i64.rem_s (local.get $a) (local.get $b)) (i64.const 0))
+      (then
+        (call $fail (i32.const 7) (local.get $span) (i32.const 1) (i32.const 0) (i32.const 0))
+        (unreachable)))
+    (i64.div_s (local.get $a) (local.get $b)))
+
+  (func $rem (param $a i64) (param $b i64) (param $span i32) (result i64)
+    (if (i64.eqz (local.get $b))
+      (then
+        (call $fail (i32.const 4) (local.get $span) (i32.const 0) (i32.const 0) (i32.const 0))
+        (unreachable)))
+    (if (i64.eq (local.get $b) (i64.const -1))
+      (then
+        (if (i32.and (i64.eq (local.get $a) (i64.const 0x8000000000000000)) (i32.eq (global.get $overflow) (i32.const 1)))
+          (then
+            (call $fail (i32.const 5) (local.get $span) (i32.const 0) (i32.const 0) (i32.const 0))
+            (unreachable)))
+        (return (i64.const 0))))
+    (i64.rem_s (local.get $a) (local.get $b)))
+
+  (func $closure (param $index i32) (param $arity i32) (param $slots i32) (param $env i32) (result i32)
+    (local $ptr i32)
+    (local.set $ptr (call $alloc (i32.const 24)))
+    (i32.store (local.get $ptr) (i32.const 3))
+    (i32.store offset=4 (local.get $ptr) (local.get $arity))
+    (i32.store offset=8 (local.get $ptr) (local.get $index))
+    (i32.store offset=12 (local.get $ptr) (local.get $env))
+    (i32.store offset=16 (local.get $ptr) (local.get $slots))
+    (local.get $ptr))
+
+  ;; Checks that $val can take $argc arguments and makes the frame they go in.
+  (func $enter (param $val i32) (param $argc i32) (param $name i32) (param $span i32) (param $func_span i32) (result i32)
+    (local $frame i32)
+    (if (i32.ne (i32.load (local.get $val)) (i32.const 3))
+      (then
+        (call $fail (i32.const 1) (local.get $func_span) (i32.load (local.get $val)) (i32.const 0) (i32.const 0))
+        (unreachable)))
+    (if (i32.ne (i32.load offset=4 (local.get $val)) (local.get $argc))
+      (then
+        (call $fail (i32.const 3) (local.get $span) (local.get $name) (i32.load offset=4 (local.get $val)) (local.get $argc))
+        (unreachable)))
+    (local.set $frame (call $alloc (i32.add (i32.const 4) (i32.shl (i32.load offset=16 (local.get $val)) (i32.const 2)))))
+    (i32.store (local.get $frame) (i32.load offset=12 (local.get $val)))
+    (local.get $frame))
+
+  ;; $check is the span of an `and`/`or` arm the callee's result must turn out a boolean for, or 0.
+  (func $tail_call (param $val i32) (param $frame i32) (param $check i32) (param $operand i32) (result i32)
+    (global.set $pending_fun (local.get $val))
+    (global.set $pending_frame (local.get $frame))
+    (global.set $pending_check (local.get $check))
+    (global.set $pending_operand (local.get $operand))
+    (i32.const 48))
+
+  (func $call (param $val i32) (param $frame i32) (param $span i32) (result i32)
+    (local $result i32)
+    (local $check i32)
+    (local $operand i32)
+    (if (i32.ge_u (global.get $depth) (global.get $max_depth))
+      (then
+        (call $fail (i32.const 6) (local.get $span) (global.get $max_depth) (i32.const 0) (i32.const 0))
+        (unreachable)))
+    (global.set $depth (i32.add (global.get $depth) (i32.const 1)))
+    (local.set $result (call_indirect (type $code) (local.get $frame) (i32.load offset=8 (local.get $val))))
+    (block $done
+      (loop $trampoline
+        (br_if $done (i32.ne (local.get $result) (i32.const 48)))
+        (if (global.get $pending_check)
+          (then
+            (local.set $check (global.get $pending_check))
+            (local.set $operand (global.get $pending_operand))))
+        (local.set $result
+          (call_indirect (type $code) (global.get $pending_frame) (i32.load offset=8 (global.get $pending_fun))))
+        (br $trampoline)))
+    (global.set $depth (i32.sub (global.get $depth) (i32.const 1)))
+    (if (local.get $check)
+      (then (drop (call $as_bool (local.get $result) (local.get $check) (local.get $operand)))))
+    (local.get $result))
+"#;
+
+pub fn emit(mut program: Program, options: &Options) -> Result<String, Error> {
+    if options.optimize {
+        optimize::optimize(&mut program, options.overflow);
+    }
+    let mut globals = Env::new();
+    resolve::resolve(&mut program, &mut globals);
+
+    let data_base = align(GLOBALS_BASE + 4 * globals.slot_names().len() as u32, 8);
+    let mut emitter = Emitter {
+        data_base,
+        ..Default::default()
+    };
+    for stmt in &program.stmts {
+        emitter.stmt(stmt)?;
+    }
+
+    let overflow = match options.overflow {
+        OverflowMode::Wrap => 0,
+        OverflowMode::Error => 1,
+        OverflowMode::Promote => 2,
+    };
+    let mut out = RUNTIME.to_string();
+    writeln!(out).unwrap();
+    writeln!(out, "  (global $overflow i32 (i32.const {}))", overflow).unwrap();
+    writeln!(out, "  (global $max_depth i32 (i32.const {}))", options.max_depth.min(i32::MAX as usize)).unwrap();
+    let heap = align(data_base + emitter.data.len() as u32, 16);
+    writeln!(out, "  (global $heap (mut i32) (i32.const {}))", heap).unwrap();
+    let data: String = emitter.data.iter().map(|byte| format!("\\{:02x}", byte)).collect();
+    writeln!(out, "  (data (i32.const {}) \"{}\")", data_base, data).unwrap();
+    writeln!(out, "  (table {} funcref)", emitter.funs.len()).unwrap();
+    if !emitter.funs.is_empty() {
+        let names: Vec<String> = (0..emitter.funs.len()).map(|index| format!("$fun_{}", index)).collect();
+        writeln!(out, "  (elem (i32.const 0) func {})", names.join(" ")).unwrap();
+    }
+    for fun in &emitter.funs {
+        write!(out, "\n{}", fun).unwrap();
+    }
+    let main = std::mem::take(&mut emitter.fun);
+    write!(out, "\n  (func (export \"main\")\n    (local $frame i32)\n{}", main.finish(false)).unwrap();
+    out.push_str(")\n");
+    Ok(out)
+}
+
+/// Decodes the arguments of a call to the imported `fail` back into the error it reports,
+/// reading spans and names from the module's `memory`.
+pub fn runtime_error(memory: &[u8], code: i32, span: i32, a: i32, b: i32, c: i32) -> Error {
+    let word = |at: u32| {
+        let at = at as usize;
+        memory.get(at..at + 4).map_or(0, |bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+    };
+    let name = |at: i32| {
+        let len = word(at as u32) as usize;
+        let start = at as usize + 4;
+        String::from_utf8_lossy(memory.get(start..start + len).unwrap_or_default()).into_owned()
+    };
+    let type_name = |tag: i32| match tag {
+        1 => "number",
+        2 => "boolean",
+        _ => "function",
+    };
+    let at = span as u32;
+    let span = Span::new(word(at) as usize, word(at + 4) as usize, word(at + 8) as usize, word(at + 12) as usize);
+    match code {
+        0 => Error::Type {
+            expected: type_name(a),
+            found: type_name(b),
+            operand: Operand::new(Operator::ALL[(c & 31) as usize], (c >> 5) as usize),
+            span,
+        },
+        1 => Error::NotCallable { found: type_name(a), span },
+        2 => Error::UnboundVariable {
+            name: name(a),
+            suggestion: None,
+            span,
+        },
+        3 => Error::Arity {
+            name: (a != 0).then(|| name(a)),
+            expected: b as usize,
+            found: c as usize,
+            span,
+        },
+        4 => Error::DivisionByZero { span },
+        5 => Error::Overflow { span },
+        6 => Error::RecursionLimit {
+            limit: a as usize,
+            backtrace: Vec::new(),
+            span,
+        },
+        _ => unsupported(if a == 0 { "big numbers" } else { "rational numbers" }, span),
+    }
+}
+
+fn unsupported(what: &'static str, span: Span) -> Error {
+    Error::Unsupported {
+        what,
+        target: "WebAssembly",
+        span,
+    }
+}
+
+fn align(addr: u32, to: u32) -> u32 {
+    addr.div_ceil(to) * to
+}
+
+/// The WebAssembly function being emitted.
+#[derive(Default)]
+struct Function {
+    body: String,
+    /// Types of the `$t` locals, numbered by position.
+    locals: Vec<&'static str>,
+    labels: usize,
+    indent: usize,
+}
+
+impl Function {
+    /// Returns the function's locals and body, for a function taking `$frame` if `param` is set.
+    fn finish(self, param: bool) -> String {
+        let mut out = String::new();
+        if param {
+            out.push_str("    (param $frame i32) (result i32)\n");
+        }
+        for (index, ty) in self.locals.iter().enumerate() {
+            writeln!(out, "    (local $t{} {})", index, ty).unwrap();
+        }
+        out.push_str(&self.body);
+        out.push_str("  )\n");
+        out
+    }
+}
+
+#[derive(Default)]
+struct Emitter {
+    /// Spans and names referred to by the code, placed at `data_base`.
+    data: Vec<u8>,
+    data_base: u32,
+    spans: HashMap<Span, u32>,
+    names: HashMap<String, u32>,
+    /// Definitions of the functions finished so far, numbered by position.
+    funs: Vec<String>,
+    fun: Function,
+}
+
+impl Emitter {
+    fn line(&mut self, line: &str) {
+        self.fun.body.push_str(&"  ".repeat(self.fun.indent + 2));
+        self.fun.body.push_str(line);
+        self.fun.body.push('\n');
+    }
+
+    fn local(&mut self, ty: &'static str) -> String {
+        self.fun.locals.push(ty);
+        format!("$t{}", self.fun.locals.len() - 1)
+    }
+
+    fn label(&mut self) -> String {
+        self.fun.labels += 1;
+        format!("$l{}", self.fun.labels)
+    }
+
+    /// Address of `span` as four little-endian words: start, end, line and column.
+    fn span(&mut self, span: Span) -> u32 {
+        if let Some(&addr) = self.spans.get(&span) {
+            return addr;
+        }
+        let addr = self.data_base + self.data.len() as u32;
+        for word in [span.start, span.end, span.line, span.col] {
+            self.data.extend((word as u32).to_le_bytes());
+        }
+        self.spans.insert(span, addr);
+        addr
+    }
+
+    /// Address of `name` as its length in bytes followed by the bytes.
+    fn name(&mut self, name: &str) -> u32 {
+        if let Some(&addr) = self.names.get(name) {
+            return addr;
+        }
+        let addr = self.data_base + self.data.len() as u32;
+        self.data.extend((name.len() as u32).to_le_bytes());
+        self.data.extend(name.as_bytes());
+        self.data.resize(align(self.data.len() as u32, 4) as usize, 0);
+        self.names.insert(name.to_string(), addr);
+        addr
+    }
+
+    fn const_span(&mut self, span: Span) {
+        let addr = self.span(span);
+        self.line(&format!("i32.const {}", addr));
+    }
+
+    /// Pushes `operand` in the form `runtime_error` decodes it from.
+    fn const_operand(&mut self, operand: Operand) {
+        self.line(&format!("i32.const {}", operand.position << 5 | operand.operator.index()));
+    }
+
+    /// Converts the value on top of the stack with `$as_num` or `$as_bool`, failing at
+    /// `span` unless it has the type `operand` needs.
+    fn convert(&mut self, func: &str, operand: Operand, span: Span) {
+        self.const_span(span);
+        self.const_operand(operand);
+        self.line(&format!("call {}", func));
+    }
+
+    /// Pushes the address holding the variable at `addr` and returns the offset to access it at.
+    fn place(&mut self, addr: Addr) -> u32 {
+        match addr {
+            Addr::Local { depth, slot } => {
+                self.line("local.get $frame");
+                for _ in 0..depth {
+                    self.line("i32.load");
+                }
+                4 + 4 * slot as u32
+            }
+            Addr::Global(slot) => {
+                self.line("i32.const 0");
+                GLOBALS_BASE + 4 * slot as u32
+            }
+        }
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) -> Result<(), Error> {
+        match &stmt.kind {
+            StmtKind::ExpStmt { exp } => {
+                self.exp(exp)?;
+                self.line("drop");
+            }
+            StmtKind::DefStmt { id, exp } => {
+                let ExpKind::Var { addr, .. } = id.kind else {
+                    unreachable!("variables are resolved before emitting")
+                };
+                let offset = self.place(addr);
+                self.exp(exp)?;
+                self.line(&format!("i32.store offset={}", offset));
+            }
+            StmtKind::PrintStmt { print_type, exp } => {
+                self.exp(exp)?;
+                let operand = Operand::new(Operator::Print(*print_type), 1);
+                match print_type {
+                    PrintType::PrintNum => {
+                        self.convert("$as_num", operand, exp.span);
+                        self.line("call $print_num");
+                    }
+                    PrintType::PrintBool => {
+                        self.convert("$as_bool", operand, exp.span);
+                        self.line("call $print_bool");
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn lambda(&mut self, lambda: &Lambda) -> Result<usize, Error> {
+        let outer = std::mem::take(&mut self.fun);
+        let result = lambda
+            .def_stmts
+            .iter()
+            .try_for_each(|stmt| self.stmt(stmt))
+            .and_then(|_| self.tail(&lambda.body, None));
+        let fun = std::mem::replace(&mut self.fun, outer);
+        result?;
+        // Nested functions were pushed first, so this one takes the next free number.
+        let index = self.funs.len();
+        self.funs.push(format!("  (func $fun_{} (type $code)\n{}", index, fun.finish(true)));
+        Ok(index)
+    }
+
+    /// Emits the callee and arguments of a call and leaves both the callee and its new frame on the stack.
+    fn call_site(&mut self, func: &Exp, args: &[Box<Exp>], span: Span) -> Result<(), Error> {
+        let name = match &func.kind {
+            ExpKind::Var { name, .. } => self.name(name),
+            _ => 0,
+        };
+        let callee = self.local("i32");
+        let frame = self.local("i32");
+        self.exp(func)?;
+        self.line(&format!("local.tee {}", callee));
+        self.line(&format!("i32.const {}", args.len()));
+        self.line(&format!("i32.const {}", name));
+        self.const_span(span);
+        self.const_span(func.span);
+        self.line("call $enter");
+        self.line(&format!("local.set {}", frame));
+        for (slot, arg) in args.iter().enumerate() {
+            self.line(&format!("local.get {}", frame));
+            self.exp(arg)?;
+            self.line(&format!("i32.store offset={}", 4 + 4 * slot));
+        }
+        self.line(&format!("local.get {}", callee));
+        self.line(&format!("local.get {}", frame));
+        Ok(())
+    }
+
+    /// Emits the arms of an `and`/`or` but the last, each branching out of `label`
+    /// as soon as it decides the result.
+    fn logical_arms(&mut self, op: LogicalOp, args: &[Box<Exp>], label: &str) -> Result<(), Error> {
+        for (i, arg) in args.iter().enumerate() {
+            self.exp(arg)?;
+            self.convert("$as_bool", Operand::new(Operator::Logical(op), i + 1), arg.span);
+            if op != LogicalOp::Or {
+                self.line("i32.eqz");
+            }
+            self.line("if");
+            self.fun.indent += 1;
+            self.line(&format!("i32.const {}", if op == LogicalOp::Or { TRUE } else { FALSE }));
+            self.line(&format!("br {}", label));
+            self.fun.indent -= 1;
+            self.line("end");
+        }
+        Ok(())
+    }
+
+    /// Emits an `if` on the condition on top of the stack, checked against `span`.
+    fn branch(
+        &mut self,
+        span: Span,
+        then: impl FnOnce(&mut Self) -> Result<(), Error>,
+        otherwise: impl FnOnce(&mut Self) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        self.convert("$as_bool", Operand::new(Operator::If, 1), span);
+        self.line("if (result i32)");
+        self.fun.indent += 1;
+        then(self)?;
+        self.fun.indent -= 1;
+        self.line("else");
+        self.fun.indent += 1;
+        otherwise(self)?;
+        self.fun.indent -= 1;
+        self.line("end");
+        Ok(())
+    }
+
+    /// Emits code leaving the value of `exp` on the stack.
+    fn exp(&mut self, exp: &Exp) -> Result<(), Error> {
+        let span = exp.span;
+        match &exp.kind {
+            ExpKind::Bool(val) => self.line(&format!("i32.const {}", if *val { TRUE } else { FALSE })),
+            ExpKind::Num(Number::Int(val)) => {
+                self.line(&format!("i64.const {}", val));
+                self.line("call $num");
+            }
+            ExpKind::Num(Number::Big(_)) => return Err(unsupported("big numbers", span)),
+            ExpKind::Num(Number::Ratio(_)) => return Err(unsupported("rational numbers", span)),
+            ExpKind::Num(Number::Float(_)) => return Err(unsupported("inexact numbers", span)),
+            ExpKind::Id(_) => unreachable!("variables are resolved before emitting"),
+            ExpKind::Var { name, addr } => {
+                let offset = self.place(*addr);
+                self.line(&format!("i32.load offset={}", offset));
+                let name = self.name(name);
+                self.line(&format!("i32.const {}", name));
+                self.const_span(span);
+                self.line("call $load");
+            }
+            ExpKind::NumExp { op, args } => {
+                if *op == NumOp::ToInexact {
+                    return Err(unsupported("inexact numbers", span));
+                }
+                // Every argument is evaluated and checked before any arithmetic happens.
+                let mut nums = Vec::new();
+                for (i, arg) in args.iter().enumerate() {
+                    self.exp(arg)?;
+                    self.convert("$as_num", Operand::new(Operator::Num(*op), i + 1), arg.span);
+                    let num = self.local("i64");
+                    self.line(&format!("local.set {}", num));
+                    nums.push(num);
+                }
+                self.line(&format!("local.get {}", nums[0]));
+                let instr = match op {
+                    NumOp::Plus => "call $add",
+                    NumOp::Minus => "call $sub",
+                    NumOp::Multiply => "call $mul",
+                    NumOp::Divide => "call $quo",
+                    NumOp::Modulus => "call $rem",
+                    NumOp::Greater => "i64.gt_s",
+                    NumOp::Smaller => "i64.lt_s",
+                    NumOp::Equal => "i64.eq",
+                    NumOp::ToExact => {
+                        self.line("call $num");
+                        return Ok(());
+                    }
+                    NumOp::ToInexact => unreachable!(),
+                };
+                if *op == NumOp::Equal {
+                    // Every adjacent pair must be equal, checking the next pair only while they are.
+                    for (i, pair) in nums.windows(2).enumerate() {
+                        if i > 0 {
+                            self.line("if (result i32)");
+                            self.fun.indent += 1;
+                            self.line(&format!("local.get {}", pair[0]));
+                        }
+                        self.line(&format!("local.get {}", pair[1]));
+                        self.line(instr);
+                    }
+                    for _ in 1..nums.len() - 1 {
+                        self.fun.indent -= 1;
+                        self.line("else");
+                        self.line("  i32.const 0");
+                        self.line("end");
+                    }
+                    self.line("call $bool");
+                } else if instr.starts_with("i64.") {
+                    self.line(&format!("local.get {}", nums[1]));
+                    self.line(instr);
+                    self.line("call $bool");
+                } else {
+                    for num in &nums[1..] {
+                        self.line(&format!("local.get {}", num));
+                        self.const_span(span);
+                        self.line(instr);
+                    }
+                    self.line("call $num");
+                }
+            }
+            ExpKind::LogicalExp { op: LogicalOp::Not, args } => {
+                self.exp(&args[0])?;
+                self.convert("$as_bool", Operand::new(Operator::Logical(LogicalOp::Not), 1), args[0].span);
+                self.line("i32.eqz");
+                self.line("call $bool");
+            }
+            ExpKind::LogicalExp { op, args } => {
+                let label = self.label();
+                let (last, args) = args.split_last().unwrap();
+                self.line(&format!("block {} (result i32)", label));
+                self.fun.indent += 1;
+                self.logical_arms(*op, args, &label)?;
+                self.exp(last)?;
+                self.convert("$as_bool", Operand::new(Operator::Logical(*op), args.len() + 1), last.span);
+                self.line("call $bool");
+                self.fun.indent -= 1;
+                self.line("end");
+            }
+            ExpKind::IfExp { cond_exp, then_exp, else_exp } => {
+                self.exp(cond_exp)?;
+                self.branch(cond_exp.span, |emitter| emitter.exp(then_exp), |emitter| emitter.exp(else_exp))?;
+            }
+            ExpKind::FunExp(lambda) => {
+                let index = self.lambda(lambda)?;
+                self.line(&format!("i32.const {}", index));
+                self.line(&format!("i32.const {}", lambda.params.len()));
+                self.line(&format!("i32.const {}", lambda.slots.len()));
+                self.line("local.get $frame");
+                self.line("call $closure");
+            }
+            ExpKind::FunCall { func, args } => {
+                self.call_site(func, args, span)?;
+                self.const_span(span);
+                self.line("call $call");
+            }
+        }
+        Ok(())
+    }
+
+    /// Emits code leaving the result of `exp`, which is in tail position, as the function's
+    /// result. `check` is an enclosing `and`/`or` arm whose value must turn out to be a boolean.
+    fn tail(&mut self, exp: &Exp, check: Option<(Operand, Span)>) -> Result<(), Error> {
+        match &exp.kind {
+            ExpKind::LogicalExp { op, args } if *op != LogicalOp::Not => {
+                let label = self.label();
+                let (last, args) = args.split_last().unwrap();
+                self.line(&format!("block {} (result i32)", label));
+                self.fun.indent += 1;
+                self.logical_arms(*op, args, &label)?;
+                self.tail(last, Some((Operand::new(Operator::Logical(*op), args.len() + 1), last.span)))?;
+                self.fun.indent -= 1;
+                self.line("end");
+            }
+            ExpKind::IfExp { cond_exp, then_exp, else_exp } => {
+                self.exp(cond_exp)?;
+                self.branch(
+                    cond_exp.span,
+                    |emitter| emitter.tail(then_exp, check),
+                    |emitter| emitter.tail(else_exp, check),
+                )?;
+            }
+            ExpKind::FunCall { func, args } => {
+                self.call_site(func, args, exp.span)?;
+                match check {
+                    Some((operand, span)) => {
+                        self.const_span(span);
+                        self.const_operand(operand);
+                    }
+                    None => {
+                        self.line("i32.const 0");
+                        self.line("i32.const 0");
+                    }
+                }
+                self.line("call $tail_call");
+            }
+            _ => {
+                self.exp(exp)?;
+                if let Some((operand, span)) = check {
+                    self.convert("$as_bool", operand, span);
+                    self.line("call $bool");
+                }
+            }
+        }
+        Ok(())
+    }
+}
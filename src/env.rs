@@ -1,83 +1,183 @@
 use std::cell::RefCell;
 use std::collections::HashMap;
+use std::fmt;
 use std::rc::Rc;
 
 use crate::ast::*;
+use crate::error::Error;
+use crate::number::Number;
+use crate::vm;
 
-#[derive(Debug, Clone)]
+/// Top-level bindings. The resolver gives every global name a slot, so lookups
+/// at run time are plain indexing.
+#[derive(Debug, Clone, Default)]
 pub struct Env {
-    vars: HashMap<String, Value>,
-    outer: Option<Rc<RefCell<Env>>>,
+    slots: HashMap<String, usize>,
+    names: Vec<String>,
+    values: Vec<Option<Value>>,
 }
 
 impl Env {
     pub fn new() -> Self {
-        Env {
-            vars: HashMap::new(),
-            outer: None,
+        Self::default()
+    }
+
+    /// Returns the slot of `name`, allocating an empty one the first time it is seen.
+    pub fn slot(&mut self, name: &str) -> usize {
+        if let Some(&slot) = self.slots.get(name) {
+            return slot;
         }
+        self.slots.insert(name.to_string(), self.names.len());
+        self.names.push(name.to_string());
+        self.values.push(None);
+        self.names.len() - 1
+    }
+
+    pub fn get(&self, slot: usize) -> Option<Value> {
+        self.values[slot].clone()
+    }
+
+    pub fn set(&mut self, slot: usize, val: Value) {
+        self.values[slot] = Some(val);
+    }
+
+    pub fn name(&self, slot: usize) -> &str {
+        &self.names[slot]
+    }
+
+    /// Names of every allocated slot, in slot order.
+    pub fn slot_names(&self) -> &[String] {
+        &self.names
+    }
+
+    /// Names of the globals that currently hold a value.
+    pub fn names(&self) -> Vec<String> {
+        self.names
+            .iter()
+            .zip(&self.values)
+            .filter(|(_, val)| val.is_some())
+            .map(|(name, _)| name.clone())
+            .collect()
     }
+}
+
+/// The slots of one function call, laid out as the resolver numbered them.
+#[derive(Debug)]
+pub struct Scope {
+    names: Rc<[String]>,
+    values: RefCell<Vec<Option<Value>>>,
+    outer: Option<Rc<Scope>>,
+}
 
-    pub fn extend(outer: Rc<RefCell<Env>>) -> Rc<RefCell<Env>> {
-        Rc::new(RefCell::new(Env {
-            vars: HashMap::new(),
-            outer: Some(outer),
-        }))
+impl Scope {
+    pub fn new(names: Rc<[String]>, outer: Option<Rc<Scope>>) -> Self {
+        let values = RefCell::new(vec![None; names.len()]);
+        Scope { names, values, outer }
     }
 
-    pub fn get_var(&self, id: &str) -> Option<Value> {
-        if let Some(val) = self.vars.get(id) {
-            Some(val.clone())
-        } else if let Some(outer) = &self.outer {
-            outer.borrow().get_var(id)
-        } else {
-            None
+    fn frame(&self, depth: usize) -> &Scope {
+        let mut scope = self;
+        for _ in 0..depth {
+            scope = scope.outer.as_ref().unwrap();
         }
+        scope
     }
 
-    pub fn set_var(&mut self, id: String, val: Value) {
-        self.vars.insert(id, val);
+    pub fn get(&self, depth: usize, slot: usize) -> Option<Value> {
+        self.frame(depth).values.borrow()[slot].clone()
+    }
+
+    pub fn name(&self, depth: usize, slot: usize) -> &str {
+        &self.frame(depth).names[slot]
+    }
+
+    pub fn set(&self, depth: usize, slot: usize, val: Value) {
+        self.frame(depth).values.borrow_mut()[slot] = Some(val);
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        let mut names = self.names.to_vec();
+        if let Some(outer) = &self.outer {
+            names.extend(outer.names());
+        }
+        names
     }
 }
 
 #[derive(Debug, Clone)]
 pub enum Value {
-    Num(i64),
+    Num(Number),
     Bool(bool),
-    Closure(Closure),
+    Closure(Rc<Closure>),
+    VmClosure(Rc<vm::Closure>),
 }
 
 impl Value {
-    pub fn to_bool(&self) -> Result<bool, (String, String)> {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Num(_) => "number",
+            Value::Bool(_) => "boolean",
+            Value::Closure(_) | Value::VmClosure(_) => "function",
+        }
+    }
+
+    pub fn to_bool(&self, operand: Operand, span: Span) -> Result<bool, Error> {
         match self {
             Value::Bool(val) => Ok(*val),
-            _ => Err((
-                "type error".to_string(),
-                "expect 'boolean' but got 'number'".to_string(),
-            )),
+            _ => Err(Error::Type {
+                expected: "boolean",
+                found: self.type_name(),
+                operand,
+                span,
+            }),
         }
     }
 
-    pub fn to_num(&self) -> Result<i64, (String, String)> {
+    pub fn to_num(&self, operand: Operand, span: Span) -> Result<Number, Error> {
         match self {
-            Value::Num(val) => Ok(*val),
-            _ => Err((
-                "type error".to_string(),
-                "expect 'number' but got 'boolean'".to_string(),
-            )),
+            Value::Num(val) => Ok(val.clone()),
+            _ => Err(Error::Type {
+                expected: "number",
+                found: self.type_name(),
+                operand,
+                span,
+            }),
+        }
+    }
+}
+
+/// Numbers print as literals, booleans as `#t`/`#f` and functions as
+/// `#<procedure name (params)>`, without the name when they have none.
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (name, params): (_, Vec<String>) = match self {
+            Value::Num(val) => return write!(f, "{}", val),
+            Value::Bool(val) => return write!(f, "{}", if *val { "#t" } else { "#f" }),
+            Value::Closure(closure) => {
+                let lambda = &closure.lambda;
+                (&lambda.name, lambda.params.iter().map(|param| param.to_string()).collect())
+            }
+            Value::VmClosure(closure) => {
+                let proto = &closure.proto;
+                (&proto.name, proto.slots[..proto.arity as usize].to_vec())
+            }
+        };
+        write!(f, "#<procedure ")?;
+        if let Some(name) = name {
+            write!(f, "{} ", name)?;
         }
+        write!(f, "({})>", params.join(" "))
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct Closure {
-    pub params: Vec<String>,
-    pub body: Box<Exp>,
-    pub env: Rc<RefCell<Env>>,
+    pub lambda: Rc<Lambda>,
+    pub env: Option<Rc<Scope>>,
 }
 
 impl Closure {
-    pub fn new(params: Vec<String>, body: Box<Exp>, env: Rc<RefCell<Env>>) -> Self {
-        Closure { params, body, env }
+    pub fn new(lambda: Rc<Lambda>, env: Option<Rc<Scope>>) -> Self {
+        Closure { lambda, env }
     }
 }
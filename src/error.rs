@@ -0,0 +1,158 @@
+use std::fmt;
+use std::io;
+
+use crate::ast::{Operand, Span};
+
+/// A function call that is still waiting for its result.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub name: Option<String>,
+    pub span: Span,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Syntax {
+        message: String,
+        span: Span,
+    },
+    Type {
+        expected: &'static str,
+        found: &'static str,
+        operand: Operand,
+        span: Span,
+    },
+    /// Found by `typecheck` before the program runs.
+    TypeMismatch {
+        expected: String,
+        found: String,
+        span: Span,
+    },
+    /// `var` would have to stand for `ty`, which contains it.
+    InfiniteType {
+        var: String,
+        ty: String,
+        span: Span,
+    },
+    UnboundVariable {
+        name: String,
+        suggestion: Option<String>,
+        span: Span,
+    },
+    Arity {
+        name: Option<String>,
+        expected: usize,
+        found: usize,
+        span: Span,
+    },
+    DivisionByZero {
+        span: Span,
+    },
+    NotCallable {
+        found: &'static str,
+        span: Span,
+    },
+    Overflow {
+        span: Span,
+    },
+    NoExactValue {
+        span: Span,
+    },
+    RecursionLimit {
+        limit: usize,
+        /// Innermost calls first.
+        backtrace: Vec<Frame>,
+        span: Span,
+    },
+    /// A feature the chosen compilation target has no equivalent for.
+    Unsupported {
+        what: &'static str,
+        target: &'static str,
+        span: Span,
+    },
+    /// A `.lspc` file that cannot be loaded.
+    Bytecode {
+        message: String,
+    },
+    Io(io::Error),
+}
+
+impl Error {
+    pub fn title(&self) -> &'static str {
+        match self {
+            Error::Syntax { .. } => "syntax error",
+            Error::Type { .. } | Error::TypeMismatch { .. } | Error::InfiniteType { .. } | Error::NotCallable { .. } => "type error",
+            Error::UnboundVariable { .. } => "unbound variable",
+            Error::Arity { .. } => "arity error",
+            Error::DivisionByZero { .. } | Error::Overflow { .. } | Error::NoExactValue { .. } => "arithmetic error",
+            Error::RecursionLimit { .. } => "recursion error",
+            Error::Unsupported { .. } => "unsupported",
+            Error::Bytecode { .. } => "bytecode error",
+            Error::Io(_) => "io error",
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match self {
+            Error::Syntax { message, .. } => message.clone(),
+            Error::Type { expected, found, operand, .. } => format!("{}: expected {}, got {}", operand, expected, found),
+            Error::TypeMismatch { expected, found, .. } => format!("expected type '{}' but found '{}'", expected, found),
+            Error::InfiniteType { var, ty, .. } => format!("infinite type: '{}' would have to be '{}'", var, ty),
+            Error::UnboundVariable { name, .. } => format!("variable '{}' not found", name),
+            Error::Arity { name, expected, found, .. } => format!(
+                "{} expects {} argument{} but got {}",
+                name.as_ref().map_or("anonymous function".to_string(), |name| format!("function '{}'", name)),
+                expected,
+                if *expected == 1 { "" } else { "s" },
+                found
+            ),
+            Error::DivisionByZero { .. } => "division by zero".to_string(),
+            Error::NotCallable { found, .. } => format!("'{}' is not callable", found),
+            Error::Overflow { .. } => "integer overflow".to_string(),
+            Error::NoExactValue { .. } => "no exact representation for a non-finite number".to_string(),
+            Error::RecursionLimit { limit, .. } => format!("maximum recursion depth of {} exceeded", limit),
+            Error::Unsupported { what, target, .. } => format!("{} cannot be compiled to {}", what, target),
+            Error::Bytecode { message } => message.clone(),
+            Error::Io(err) => err.to_string(),
+        }
+    }
+
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            Error::Syntax { span, .. }
+            | Error::Type { span, .. }
+            | Error::TypeMismatch { span, .. }
+            | Error::InfiniteType { span, .. }
+            | Error::UnboundVariable { span, .. }
+            | Error::Arity { span, .. }
+            | Error::DivisionByZero { span }
+            | Error::NotCallable { span, .. }
+            | Error::Overflow { span }
+            | Error::NoExactValue { span }
+            | Error::RecursionLimit { span, .. }
+            | Error::Unsupported { span, .. } => Some(*span),
+            Error::Bytecode { .. } | Error::Io(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.title(), self.message())
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
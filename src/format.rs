@@ -0,0 +1,246 @@
+use crate::ast::*;
+
+/// Lines are broken to stay within this many columns where the nesting allows.
+const WIDTH: usize = 80;
+/// How much further the body of a `define` or `fun` is indented than its opening paren.
+const INDENT: usize = 2;
+
+/// A form before it is laid out.
+enum Doc {
+    Atom(String),
+    List(Vec<Doc>, Style),
+    /// A comment inside a list, which is `trailing` when nothing but spaces
+    /// separated it from what came before it.
+    Comment { text: String, trailing: bool },
+}
+
+/// Where the items of a list that does not fit on one line go.
+#[derive(Clone, Copy)]
+enum Style {
+    /// The first item after the head stays on the head's line and the rest are aligned under it.
+    Align,
+    /// The given number of items after the head stay on the head's line and the rest go
+    /// on their own lines, indented by `INDENT`.
+    Body(usize),
+}
+
+/// Lays `program` out in the canonical style: one statement per line, with blank
+/// lines between statements kept (at most one in a row). A form that does not fit
+/// within `WIDTH` columns is broken Lisp-style, with the bodies of `define` and `fun`
+/// indented and the arguments of anything else aligned under the first one.
+///
+/// `source` is the text `program` was parsed from, so that number literals keep the
+/// way they were written and comments stay where they were. A comment inside a
+/// form keeps its place among the form's items, except that one before the first
+/// item goes after it, and a line comment breaks the form over several lines.
+pub fn format(program: &Program, source: &str) -> String {
+    let formatter = Formatter { source, comments: &program.comments };
+    let mut out = String::new();
+    let mut last_end = None;
+    let mut from = 0;
+    for stmt in &program.stmts {
+        for comment in formatter.between(from, stmt.span.start) {
+            formatter.top_level_comment(&mut out, comment, last_end);
+            last_end = Some(comment.span.end);
+        }
+        formatter.separate(&mut out, last_end, stmt.span.start);
+        out.push_str(&render(&formatter.stmt_doc(stmt), 0));
+        out.push('\n');
+        last_end = Some(stmt.span.end);
+        from = stmt.span.end;
+    }
+    for comment in formatter.between(from, source.len()) {
+        formatter.top_level_comment(&mut out, comment, last_end);
+        last_end = Some(comment.span.end);
+    }
+    out
+}
+
+fn atom(text: &str) -> Doc {
+    Doc::Atom(text.to_string())
+}
+
+struct Formatter<'a> {
+    source: &'a str,
+    /// Every comment of the program in source order.
+    comments: &'a [Comment],
+}
+
+impl Formatter<'_> {
+    /// The comments that start at or after `start` and end by `end`.
+    fn between(&self, start: usize, end: usize) -> &[Comment] {
+        let first = self.comments.partition_point(|comment| comment.span.start < start);
+        let last = self.comments.partition_point(|comment| comment.span.end <= end);
+        &self.comments[first..last.max(first)]
+    }
+
+    fn is_trailing(&self, last_end: usize, comment: &Comment) -> bool {
+        !self.source[last_end..comment.span.start].contains('\n')
+    }
+
+    /// Keeps one blank line where there was at least one between what ended at
+    /// `last_end` and what starts at `start`.
+    fn separate(&self, out: &mut String, last_end: Option<usize>, start: usize) {
+        if let Some(end) = last_end {
+            if self.source[end..start].matches('\n').count() > 1 {
+                out.push('\n');
+            }
+        }
+    }
+
+    /// Adds a comment outside of every statement either to the end of the last line or on a line of its own.
+    fn top_level_comment(&self, out: &mut String, comment: &Comment, last_end: Option<usize>) {
+        match last_end {
+            Some(end) if self.is_trailing(end, comment) => {
+                out.pop();
+                out.push(' ');
+            }
+            _ => self.separate(out, last_end, comment.span.start),
+        }
+        out.push_str(&comment.text);
+        out.push('\n');
+    }
+
+    /// A list of `items` spanning `span`, with the comments inside it that are not
+    /// inside one of the items. Items without a span, like the parameters of a
+    /// function, have their comments put after them.
+    fn list(&self, span: Span, items: Vec<(Doc, Option<Span>)>, style: Style) -> Doc {
+        let mut docs = Vec::new();
+        let mut from = span.start;
+        let mut before_head = &[][..];
+        for (index, (doc, item_span)) in items.into_iter().enumerate() {
+            if let Some(item_span) = item_span {
+                let comments = self.between(from, item_span.start);
+                if index == 0 {
+                    before_head = comments;
+                } else {
+                    self.push_comments(&mut docs, from, comments);
+                }
+                from = item_span.end;
+            }
+            docs.push(doc);
+            if index == 0 {
+                self.push_comments(&mut docs, span.start, before_head);
+            }
+        }
+        self.push_comments(&mut docs, from, self.between(from, span.end));
+        Doc::List(docs, style)
+    }
+
+    fn push_comments(&self, docs: &mut Vec<Doc>, mut last_end: usize, comments: &[Comment]) {
+        for comment in comments {
+            let trailing = self.is_trailing(last_end, comment);
+            docs.push(Doc::Comment { text: comment.text.clone(), trailing });
+            last_end = comment.span.end;
+        }
+    }
+
+    fn stmt_doc(&self, stmt: &Stmt) -> Doc {
+        match &stmt.kind {
+            StmtKind::ExpStmt { exp } => self.exp_doc(exp),
+            StmtKind::PrintStmt { print_type, exp } => {
+                let head = Operator::Print(*print_type).to_string();
+                self.list(stmt.span, vec![(Doc::Atom(head), None), (self.exp_doc(exp), Some(exp.span))], Style::Align)
+            }
+            StmtKind::DefStmt { id, exp } => {
+                let items = vec![(atom("define"), None), (Doc::Atom(id.to_string()), Some(id.span)), (self.exp_doc(exp), Some(exp.span))];
+                self.list(stmt.span, items, Style::Body(1))
+            }
+        }
+    }
+
+    fn exp_doc(&self, exp: &Exp) -> Doc {
+        let list = |head: (Doc, Option<Span>), args: &[Box<Exp>]| {
+            let items = std::iter::once(head).chain(args.iter().map(|arg| (self.exp_doc(arg), Some(arg.span)))).collect();
+            self.list(exp.span, items, Style::Align)
+        };
+        match &exp.kind {
+            ExpKind::Num(_) => atom(&self.source[exp.span.start..exp.span.end]),
+            ExpKind::Bool(_) | ExpKind::Id(_) | ExpKind::Var { .. } => Doc::Atom(exp.to_string()),
+            ExpKind::NumExp { op, args } => list((Doc::Atom(op.to_string()), None), args),
+            ExpKind::LogicalExp { op, args } => list((Doc::Atom(op.to_string()), None), args),
+            ExpKind::IfExp { cond_exp, then_exp, else_exp } => {
+                let items = [cond_exp, then_exp, else_exp].into_iter().map(|exp| (self.exp_doc(exp), Some(exp.span)));
+                self.list(exp.span, std::iter::once((atom("if"), None)).chain(items).collect(), Style::Align)
+            }
+            ExpKind::FunExp(lambda) => {
+                let params: Vec<String> = lambda.params.iter().map(|param| param.to_string()).collect();
+                let mut items = vec![(atom("fun"), None), (Doc::Atom(format!("({})", params.join(" "))), None)];
+                items.extend(lambda.def_stmts.iter().map(|stmt| (self.stmt_doc(stmt), Some(stmt.span))));
+                items.push((self.exp_doc(&lambda.body), Some(lambda.body.span)));
+                self.list(exp.span, items, Style::Body(1))
+            }
+            ExpKind::FunCall { func, args } => list((self.exp_doc(func), Some(func.span)), args),
+        }
+    }
+}
+
+/// `doc` on a single line, unless it holds a comment that ends its line or spans several.
+fn flat(doc: &Doc) -> Option<String> {
+    match doc {
+        Doc::Atom(text) => Some(text.clone()),
+        Doc::List(items, _) => Some(format!("({})", items.iter().map(flat).collect::<Option<Vec<_>>>()?.join(" "))),
+        Doc::Comment { text, .. } if text.starts_with(';') || text.contains('\n') => None,
+        Doc::Comment { text, .. } => Some(text.clone()),
+    }
+}
+
+/// Lays `doc` out starting at column `col`. Every line after the first is indented with spaces.
+fn render(doc: &Doc, col: usize) -> String {
+    let flat = flat(doc);
+    let Doc::List(items, style) = doc else { return flat.unwrap() };
+    if let Some(flat) = flat.filter(|flat| col + flat.len() <= WIDTH) {
+        return flat;
+    }
+
+    let head = render(&items[0], col + 1);
+    let mut out = format!("({}", head);
+    let (inline, indent) = match style {
+        // A head spanning several lines leaves nothing to align under.
+        _ if head.contains('\n') => (0, col + 1),
+        Style::Align if items.len() > 1 => (1, col + 2 + head.len()),
+        Style::Align => (0, col + 1),
+        Style::Body(inline) => (*inline, col + INDENT),
+    };
+    let mut line_col = col + 1 + head.len();
+    let mut on_head_line = true;
+    let mut placed = 0;
+    let mut after_line_comment = false;
+    for item in &items[1..] {
+        if let Doc::Comment { text, trailing } = item {
+            if *trailing && !after_line_comment {
+                out.push(' ');
+            } else {
+                out.push('\n');
+                out.push_str(&" ".repeat(indent));
+            }
+            out.push_str(text);
+            // Whatever follows a comment starts a new line.
+            on_head_line = false;
+            after_line_comment = text.starts_with(';');
+            continue;
+        }
+        if on_head_line && placed < inline {
+            let text = render(item, line_col + 1);
+            out.push(' ');
+            out.push_str(&text);
+            line_col = match text.rfind('\n') {
+                Some(at) => text.len() - at - 1,
+                None => line_col + 1 + text.len(),
+            };
+        } else {
+            on_head_line = false;
+            out.push('\n');
+            out.push_str(&" ".repeat(indent));
+            out.push_str(&render(item, indent));
+        }
+        placed += 1;
+        after_line_comment = false;
+    }
+    if after_line_comment {
+        out.push('\n');
+        out.push_str(&" ".repeat(indent));
+    }
+    out.push(')');
+    out
+}
@@ -1,118 +1,311 @@
 use std::cell::RefCell;
+use std::cmp::Ordering;
 use std::io::Write;
 use std::rc::Rc;
 use std::result::Result;
 
 use crate::ast::*;
+use crate::diagnostic;
 use crate::env::*;
+use crate::error::{Error, Frame};
+use crate::number::{ArithError, Number, OverflowMode};
+use crate::optimize;
+use crate::resolve;
 
-pub fn run<W: Write>(program: Program, writer: &mut W) -> Result<(), (String, String)> {
+/// Number of innermost calls kept in the backtrace of a recursion error.
+pub(crate) const BACKTRACE_LEN: usize = 5;
+
+/// Native stack that must be left before evaluating a subexpression, generous enough
+/// for one level of unoptimized evaluation.
+const RED_ZONE: usize = 64 * 1024;
+/// Native stack added whenever less than `RED_ZONE` is left.
+const STACK_GROWTH: usize = 4 << 20;
+
+/// Which engine runs a program.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Backend {
+    /// Walks the syntax tree directly.
+    #[default]
+    Tree,
+    /// Compiles to bytecode and runs it on the virtual machine in `vm`.
+    Vm,
+}
+
+impl Backend {
+    pub fn parse(backend: &str) -> Option<Self> {
+        match backend {
+            "tree" => Some(Backend::Tree),
+            "vm" => Some(Backend::Vm),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Options {
+    pub backend: Backend,
+    pub overflow: OverflowMode,
+    /// Maximum number of nested non-tail calls. The tree-walker grows its native
+    /// stack as it goes, so any thread can run it up to this depth.
+    pub max_depth: usize,
+    /// Run the program through `optimize` first.
+    pub optimize: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            backend: Backend::default(),
+            overflow: OverflowMode::default(),
+            max_depth: 1000,
+            optimize: false,
+        }
+    }
+}
+
+pub struct Context<'a, W: Write> {
+    pub writer: &'a mut W,
+    pub options: Options,
+    pub(crate) frames: Vec<Frame>,
+}
+
+impl<'a, W: Write> Context<'a, W> {
+    pub fn new(writer: &'a mut W, options: Options) -> Self {
+        Context {
+            writer,
+            options,
+            frames: Vec::new(),
+        }
+    }
+}
+
+pub fn run<W: Write>(program: Program, writer: &mut W) -> Result<(), Error> {
+    run_with_options(program, writer, Options::default())
+}
+
+pub fn run_with_options<W: Write>(mut program: Program, writer: &mut W, options: Options) -> Result<(), Error> {
+    if options.optimize {
+        optimize::optimize(&mut program, options.overflow);
+    }
     let env = Rc::new(RefCell::new(Env::new()));
+    let mut ctx = Context::new(writer, options);
     for stmt in program.stmts {
-        eval_stmt(stmt, env.clone(), writer)?;
+        eval_stmt(stmt, env.clone(), &mut ctx)?;
     }
     Ok(())
 }
 
-fn eval_stmt<W: Write>(stmt: Stmt, env: Rc<RefCell<Env>>, writer: &mut W) -> Result<(), (String, String)> {
-    match stmt {
-        Stmt::ExpStmt { exp } => {
-            eval_exp(exp, env.clone(), writer)?;
+/// Evaluates one top-level statement in `env`, returning the value of a bare expression statement.
+pub fn eval_stmt<W: Write>(mut stmt: Stmt, env: Rc<RefCell<Env>>, ctx: &mut Context<W>) -> Result<Option<Value>, Error> {
+    resolve::resolve_stmt(&mut stmt, &mut env.borrow_mut());
+    exec_stmt(&stmt, &env, None, ctx)
+}
+
+fn exec_stmt<W: Write>(
+    stmt: &Stmt,
+    env: &Rc<RefCell<Env>>,
+    scope: Option<&Rc<Scope>>,
+    ctx: &mut Context<W>,
+) -> Result<Option<Value>, Error> {
+    match &stmt.kind {
+        StmtKind::ExpStmt { exp } => {
+            return Ok(Some(eval_exp(exp, env, scope, ctx)?));
         }
-        Stmt::DefStmt { id, exp } => {
-            let id_str = id.to_string();
-            let val = eval_exp(exp, env.clone(), writer)?;
-            env.borrow_mut().set_var(id_str, val);
+        StmtKind::DefStmt { id, exp } => {
+            let val = eval_exp(exp, env, scope, ctx)?;
+            match id.kind {
+                ExpKind::Var { addr: Addr::Local { depth, slot }, .. } => scope.unwrap().set(depth, slot, val),
+                ExpKind::Var { addr: Addr::Global(slot), .. } => env.borrow_mut().set(slot, val),
+                _ => unreachable!("variables are resolved before evaluation"),
+            }
         }
-        Stmt::PrintStmt { exp, print_type } => {
-            let val = eval_exp(exp, env.clone(), writer)?;
+        StmtKind::PrintStmt { exp, print_type } => {
+            let val = eval_exp(exp, env, scope, ctx)?;
             match print_type {
                 PrintType::PrintNum => {
-                    writeln!(writer, "{}", val.to_num()?).unwrap();
+                    writeln!(ctx.writer, "{}", val.to_num(Operand::new(Operator::Print(*print_type), 1), exp.span)?)?;
                 }
                 PrintType::PrintBool => {
-                    writeln!(writer, "{}", if val.to_bool()? { "#t" } else { "#f" }).unwrap();
+                    let val = val.to_bool(Operand::new(Operator::Print(*print_type), 1), exp.span)?;
+                    writeln!(ctx.writer, "{}", if val { "#t" } else { "#f" })?;
                 }
             };
         }
     }
-    Ok(())
+    Ok(None)
 }
 
-fn eval_exp<W: Write>(exp: Exp, env: Rc<RefCell<Env>>, writer: &mut W) -> Result<Value, (String, String)> {
-    match exp {
-        Exp::Bool(val) => Ok(Value::Bool(val)),
-        Exp::Num(val) => Ok(Value::Num(val)),
-        Exp::Id(val) => match env.borrow().get_var(&val) {
-            Some(val) => Ok(val),
-            None => Err(("syntax error".to_string(), format!("variable '{}' not found", val))),
-        },
-        Exp::NumExp { op, args } => {
-            let args = args
-                .iter()
-                .map(|arg| eval_exp(*arg.clone(), env.clone(), writer)?.to_num())
-                .collect::<Result<Vec<i64>, (String, String)>>()?;
-            match op {
-                NumOp::Plus => Ok(Value::Num(args.iter().sum())),
-                NumOp::Minus => Ok(Value::Num(args[0] - args[1])),
-                NumOp::Multiply => Ok(Value::Num(args.iter().product())),
-                NumOp::Divide => Ok(Value::Num(args[0] / args[1])),
-                NumOp::Modulus => Ok(Value::Num(args[0] % args[1])),
-                NumOp::Greater => Ok(Value::Bool(args[0] > args[1])),
-                NumOp::Smaller => Ok(Value::Bool(args[0] < args[1])),
-                NumOp::Equal => Ok(Value::Bool(args[0] == args[1])),
+fn arith_error(err: ArithError, span: Span) -> Error {
+    match err {
+        ArithError::Overflow => Error::Overflow { span },
+        ArithError::DivisionByZero => Error::DivisionByZero { span },
+        ArithError::NoExactValue => Error::NoExactValue { span },
+    }
+}
+
+/// Applies `op` to already type-checked arguments; `span` locates arithmetic errors.
+pub(crate) fn apply_num_op(op: NumOp, args: &[Number], mode: OverflowMode, span: Span) -> Result<Value, Error> {
+    let arith = |result: Result<Number, ArithError>| result.map(Value::Num).map_err(|err| arith_error(err, span));
+    match op {
+        NumOp::Plus => arith(args[1..].iter().try_fold(args[0].clone(), |acc, arg| acc.add(arg, mode))),
+        NumOp::Minus => arith(args[0].sub(&args[1], mode)),
+        NumOp::Multiply => arith(args[1..].iter().try_fold(args[0].clone(), |acc, arg| acc.mul(arg, mode))),
+        NumOp::Divide => arith(args[0].div(&args[1], mode)),
+        NumOp::Modulus => arith(args[0].rem(&args[1], mode)),
+        NumOp::Greater => Ok(Value::Bool(args[0] > args[1])),
+        NumOp::Smaller => Ok(Value::Bool(args[0] < args[1])),
+        NumOp::Equal => Ok(Value::Bool(args.windows(2).all(|pair| pair[0].partial_cmp(&pair[1]) == Some(Ordering::Equal)))),
+        NumOp::ToInexact => Ok(Value::Num(args[0].to_inexact())),
+        NumOp::ToExact => arith(args[0].to_exact()),
+    }
+}
+
+pub(crate) fn unbound(name: &str, env: &Rc<RefCell<Env>>, scope: Option<&Rc<Scope>>, span: Span) -> Error {
+    let mut names = env.borrow().names();
+    if let Some(scope) = scope {
+        names.extend(scope.names());
+    }
+    let suggestion = diagnostic::suggest(name, names.iter().map(String::as_str));
+    Error::UnboundVariable { name: name.to_string(), suggestion, span }
+}
+
+/// What is left to do once an expression has been evaluated up to its tail position.
+enum Step {
+    Done(Value),
+    /// Run the body of the closure in the given frame, in place of the current call.
+    Call(Rc<Closure>, Rc<Scope>),
+}
+
+/// Calls in tail position (the branches of `if`, the last arm of `and`/`or` and a
+/// function body) are handed back here instead of recursing, so tail recursion runs
+/// in constant stack. Such a call replaces the frame above `base` rather than pushing a new one.
+fn eval_exp<W: Write>(exp: &Exp, env: &Rc<RefCell<Env>>, scope: Option<&Rc<Scope>>, ctx: &mut Context<W>) -> Result<Value, Error> {
+    // Other calls and nested expressions recurse, onto a new stack segment when this one runs low.
+    stacker::maybe_grow(RED_ZONE, STACK_GROWTH, || eval_nested(exp, env, scope, ctx))
+}
+
+fn eval_nested<W: Write>(exp: &Exp, env: &Rc<RefCell<Env>>, scope: Option<&Rc<Scope>>, ctx: &mut Context<W>) -> Result<Value, Error> {
+    let base = ctx.frames.len();
+    // A tail `and`/`or` arm whose value must still turn out to be a boolean.
+    let mut expect_bool = None;
+    let mut step = eval_step(exp, env, scope, ctx, base, &mut expect_bool);
+    while let Ok(Step::Call(closure, scope)) = step {
+        step = eval_step(&closure.lambda.body, env, Some(&scope), ctx, base, &mut expect_bool);
+    }
+    ctx.frames.truncate(base);
+    let Step::Done(val) = step? else { unreachable!() };
+    match expect_bool {
+        Some((operand, span)) => val.to_bool(operand, span).map(Value::Bool),
+        None => Ok(val),
+    }
+}
+
+fn eval_step<W: Write>(
+    exp: &Exp,
+    env: &Rc<RefCell<Env>>,
+    scope: Option<&Rc<Scope>>,
+    ctx: &mut Context<W>,
+    base: usize,
+    expect_bool: &mut Option<(Operand, Span)>,
+) -> Result<Step, Error> {
+    let mut exp = exp;
+    let val = loop {
+        let span = exp.span;
+        match &exp.kind {
+            ExpKind::Bool(val) => break Value::Bool(*val),
+            ExpKind::Num(val) => break Value::Num(val.clone()),
+            ExpKind::Id(_) => unreachable!("variables are resolved before evaluation"),
+            ExpKind::Var { name, addr } => {
+                let val = match *addr {
+                    Addr::Local { depth, slot } => scope.unwrap().get(depth, slot),
+                    Addr::Global(slot) => env.borrow().get(slot),
+                };
+                match val {
+                    Some(val) => break val,
+                    None => return Err(unbound(name, env, scope, span)),
+                }
             }
-        }
-        Exp::LogicalExp { op, args } => {
-            let args = args
-                .iter()
-                .map(|arg| eval_exp(*arg.clone(), env.clone(), writer)?.to_bool())
-                .collect::<Result<Vec<bool>, (String, String)>>()?;
-            match op {
-                LogicalOp::And => Ok(Value::Bool(args.iter().all(|&x| x))),
-                LogicalOp::Or => Ok(Value::Bool(args.iter().any(|&x| x))),
-                LogicalOp::Not => Ok(Value::Bool(!args[0])),
+            ExpKind::NumExp { op, args } => {
+                let args = args
+                    .iter()
+                    .enumerate()
+                    .map(|(i, arg)| eval_exp(arg, env, scope, ctx)?.to_num(Operand::new(Operator::Num(*op), i + 1), arg.span))
+                    .collect::<Result<Vec<Number>, Error>>()?;
+                break apply_num_op(*op, &args, ctx.options.overflow, span)?;
             }
-        }
-        Exp::IfExp {
-            cond_exp,
-            then_exp,
-            else_exp,
-        } => {
-            if eval_exp(*cond_exp, env.clone(), writer)?.to_bool()? {
-                eval_exp(*then_exp, env.clone(), writer)
-            } else {
-                eval_exp(*else_exp, env.clone(), writer)
+            ExpKind::LogicalExp { op: LogicalOp::Not, args } => {
+                let operand = Operand::new(Operator::Logical(LogicalOp::Not), 1);
+                break Value::Bool(!eval_exp(&args[0], env, scope, ctx)?.to_bool(operand, args[0].span)?);
             }
-        }
-        Exp::FunExp {
-            params,
-            def_stmts,
-            body,
-        } => {
-            let new_env = Env::extend(env.clone());
-            for stmt in def_stmts {
-                eval_stmt(stmt, new_env.clone(), writer)?;
+            ExpKind::LogicalExp { op, args } => {
+                // `and` stops at the first false arm and `or` at the first true one.
+                let (last, args) = args.split_last().unwrap();
+                let stop = *op == LogicalOp::Or;
+                for (i, arg) in args.iter().enumerate() {
+                    let operand = Operand::new(Operator::Logical(*op), i + 1);
+                    if eval_exp(arg, env, scope, ctx)?.to_bool(operand, arg.span)? == stop {
+                        return Ok(Step::Done(Value::Bool(stop)));
+                    }
+                }
+                *expect_bool = Some((Operand::new(Operator::Logical(*op), args.len() + 1), last.span));
+                exp = last;
             }
-            Ok(Value::Closure(Closure::new(
-                params.iter().map(|param| param.to_string()).collect(),
-                body,
-                new_env,
-            )))
-        }
-        Exp::FunCall { func, args } => {
-            let fun_exp = eval_exp(*func, env.clone(), writer)?;
-            match fun_exp {
-                Value::Closure(closure) => {
-                    let new_env = Env::extend(closure.env.clone());
-                    for (param, arg) in closure.params.iter().zip(args) {
-                        let arg_val = eval_exp(*arg, env.clone(), writer)?;
-                        new_env.borrow_mut().set_var(param.to_string(), arg_val);
+            ExpKind::IfExp {
+                cond_exp,
+                then_exp,
+                else_exp,
+            } => {
+                exp = if eval_exp(cond_exp, env, scope, ctx)?.to_bool(Operand::new(Operator::If, 1), cond_exp.span)? {
+                    then_exp
+                } else {
+                    else_exp
+                };
+            }
+            ExpKind::FunExp(lambda) => {
+                break Value::Closure(Rc::new(Closure::new(lambda.clone(), scope.cloned())));
+            }
+            ExpKind::FunCall { func, args } => {
+                let name = match &func.kind {
+                    ExpKind::Id(name) | ExpKind::Var { name, .. } => Some(name.clone()),
+                    _ => None,
+                };
+                let closure = match eval_exp(func, env, scope, ctx)? {
+                    Value::Closure(closure) => closure,
+                    val => {
+                        return Err(Error::NotCallable {
+                            found: val.type_name(),
+                            span: func.span,
+                        })
                     }
-                    eval_exp(*closure.body.clone(), new_env, writer)
+                };
+                if closure.lambda.params.len() != args.len() {
+                    return Err(Error::Arity {
+                        name,
+                        expected: closure.lambda.params.len(),
+                        found: args.len(),
+                        span,
+                    });
+                }
+                let new_scope = Rc::new(Scope::new(closure.lambda.slots.clone(), closure.env.clone()));
+                for (slot, arg) in args.iter().enumerate() {
+                    new_scope.set(0, slot, eval_exp(arg, env, scope, ctx)?);
                 }
-                _ => unreachable!()
+                ctx.frames.truncate(base);
+                if ctx.frames.len() >= ctx.options.max_depth {
+                    return Err(Error::RecursionLimit {
+                        limit: ctx.options.max_depth,
+                        backtrace: ctx.frames.iter().rev().take(BACKTRACE_LEN).cloned().collect(),
+                        span,
+                    });
+                }
+                ctx.frames.push(Frame { name, span });
+                // Internal definitions see the parameters and each other (letrec*).
+                for stmt in &closure.lambda.def_stmts {
+                    exec_stmt(stmt, env, Some(&new_scope), ctx)?;
+                }
+                return Ok(Step::Call(closure, new_scope));
             }
         }
-    }
+    };
+    Ok(Step::Done(val))
 }
@@ -1,4 +1,18 @@
 pub mod ast;
+pub mod bytecode;
+pub mod check;
+pub mod compiler;
+pub mod diagnostic;
+pub mod emit_c;
+pub mod emit_wat;
 pub mod env;
+pub mod error;
+pub mod format;
 pub mod interpreter;
+pub mod number;
+pub mod optimize;
 pub mod parser;
+pub mod repl;
+pub mod resolve;
+pub mod typecheck;
+pub mod vm;
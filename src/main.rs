@@ -1,44 +1,435 @@
-use mini_lisp::interpreter;
+use std::path::Path;
+
+use mini_lisp::bytecode;
+use mini_lisp::check;
+use mini_lisp::compiler;
+use mini_lisp::diagnostic::{ColorChoice, Diagnostic};
+use mini_lisp::emit_c;
+use mini_lisp::emit_wat;
+use mini_lisp::error::Error;
+use mini_lisp::format;
+use mini_lisp::interpreter::{self, Backend};
+use mini_lisp::number::OverflowMode;
+use mini_lisp::optimize;
 use mini_lisp::parser;
+use mini_lisp::repl;
+use mini_lisp::typecheck;
+use mini_lisp::vm;
 use colored::Colorize;
 
+const USAGE: &str = "Usage: mini-lisp [--color=auto|always|never] [--check-arity] [--typecheck] [-O0|-O1] [--overflow=wrap|error|promote] [--max-depth=N] [--backend=tree|vm] [file]
+       mini-lisp compile [--color=auto|always|never] [--check-arity] [--typecheck] [-O0|-O1] <file> [-o <output>]
+       mini-lisp emit-c [--color=auto|always|never] [--check-arity] [--typecheck] [-O0|-O1] [--overflow=wrap|error] [--max-depth=N] <file> [-o <output>]
+       mini-lisp emit-wat [--color=auto|always|never] [--check-arity] [--typecheck] [-O0|-O1] [--overflow=wrap|error] [--max-depth=N] <file> [-o <output>]
+       mini-lisp fmt [--color=auto|always|never] [--check | --write | -o <output>] <file>";
+
+#[derive(PartialEq)]
+enum Command {
+    Run,
+    /// Write the program's bytecode to a `.lspc` file.
+    Compile,
+    /// Write the program as C source.
+    EmitC,
+    /// Write the program as a WebAssembly text module.
+    EmitWat,
+    /// Rewrite the program in the canonical layout.
+    Fmt,
+}
+
+impl Command {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "compile" => Some(Command::Compile),
+            "emit-c" => Some(Command::EmitC),
+            "emit-wat" => Some(Command::EmitWat),
+            "fmt" => Some(Command::Fmt),
+            _ => None,
+        }
+    }
+
+    fn describe(&self) -> &'static str {
+        match self {
+            Command::Run => "running a program",
+            Command::Compile => "'compile'",
+            Command::EmitC => "'emit-c'",
+            Command::EmitWat => "'emit-wat'",
+            Command::Fmt => "'fmt'",
+        }
+    }
+
+    /// Whether `flag`, written without its value, means anything to this command.
+    fn accepts(&self, flag: &str) -> bool {
+        match flag {
+            "--color" => true,
+            "--check" | "--write" => *self == Command::Fmt,
+            "--backend" => *self == Command::Run,
+            "-o" => *self != Command::Run,
+            "--overflow" | "--max-depth" => matches!(self, Command::Run | Command::EmitC | Command::EmitWat),
+            "--check-arity" | "--typecheck" | "-O" => *self != Command::Fmt,
+            _ => true,
+        }
+    }
+
+    fn extension(&self) -> &'static str {
+        match self {
+            Command::Run | Command::Fmt => unreachable!(),
+            Command::Compile => "lspc",
+            Command::EmitC => "c",
+            Command::EmitWat => "wat",
+        }
+    }
+}
+
+struct Args {
+    command: Command,
+    path: Option<String>,
+    /// Where a subcommand writes its result, next to the source file by default.
+    output: Option<String>,
+    color: ColorChoice,
+    /// Only check that the file is formatted.
+    check: bool,
+    /// Format the file in place.
+    write: bool,
+    check_arity: bool,
+    typecheck: bool,
+    options: interpreter::Options,
+}
+
+fn parse_args() -> Args {
+    let mut args = Args {
+        command: Command::Run,
+        path: None,
+        output: None,
+        color: ColorChoice::Auto,
+        check: false,
+        write: false,
+        check_arity: false,
+        typecheck: false,
+        options: interpreter::Options::default(),
+    };
+    let mut overflow = None;
+    let mut argv = std::env::args().skip(1).peekable();
+    if let Some(command) = argv.peek().and_then(|arg| Command::parse(arg)) {
+        argv.next();
+        args.command = command;
+    }
+    while let Some(arg) = argv.next() {
+        let flag = match arg.split_once('=') {
+            Some((flag, _)) => flag,
+            None if arg.starts_with("-O") => "-O",
+            None => &arg,
+        };
+        if flag.starts_with('-') && !args.command.accepts(flag) {
+            usage_error(&format!("'{}' does not apply to {}", flag, args.command.describe()));
+        }
+        if let Some(choice) = arg.strip_prefix("--color=") {
+            args.color = ColorChoice::parse(choice).unwrap_or_else(|| usage_error(&format!("invalid color choice '{}'", choice)));
+        } else if let Some(mode) = arg.strip_prefix("--overflow=") {
+            overflow = Some(OverflowMode::parse(mode).unwrap_or_else(|| usage_error(&format!("invalid overflow mode '{}'", mode))));
+        } else if let Some(backend) = arg.strip_prefix("--backend=") {
+            args.options.backend = Backend::parse(backend).unwrap_or_else(|| usage_error(&format!("invalid backend '{}'", backend)));
+        } else if let Some(depth) = arg.strip_prefix("--max-depth=") {
+            args.options.max_depth = depth.parse().unwrap_or_else(|_| usage_error(&format!("invalid maximum depth '{}'", depth)));
+        } else if arg == "--check" {
+            args.check = true;
+        } else if arg == "--write" {
+            args.write = true;
+        } else if arg == "--check-arity" {
+            args.check_arity = true;
+        } else if arg == "--typecheck" {
+            args.typecheck = true;
+        } else if arg == "-O0" || arg == "-O1" {
+            args.options.optimize = arg == "-O1";
+        } else if arg == "-o" {
+            args.output = Some(argv.next().unwrap_or_else(|| usage_error("'-o' needs an output file")));
+        } else if arg.starts_with('-') || args.path.is_some() {
+            usage_error(&format!("unexpected argument '{}'", arg));
+        } else {
+            args.path = Some(arg);
+        }
+    }
+    if args.command != Command::Run && args.path.is_none() {
+        usage_error("a source file is needed");
+    }
+    if args.path.is_none() && (args.check_arity || args.typecheck) {
+        usage_error("'--check-arity' and '--typecheck' need a source file");
+    }
+    if [args.check, args.write, args.output.is_some()].iter().filter(|&&set| set).count() > 1 {
+        usage_error("choose only one of '--check', '--write' and '-o'");
+    }
+    args.options.overflow = match args.command {
+        // Compiled programs have no big numbers, so overflowing is an error unless they wrap.
+        Command::EmitC | Command::EmitWat => match overflow {
+            Some(OverflowMode::Promote) => usage_error("big numbers cannot be compiled, choose --overflow=wrap or --overflow=error"),
+            overflow => overflow.unwrap_or(OverflowMode::Error),
+        },
+        _ => overflow.unwrap_or_default(),
+    };
+    args
+}
+
+fn usage_error(message: &str) -> ! {
+    eprintln!("{}: {}\n{}", "error".red().bold(), message, USAGE.underline());
+    std::process::exit(1);
+}
+
 fn main() {
-    let path = std::env::args().nth(1).unwrap_or_else(|| {
-        eprintln!("{}: Please provide a file path!\n{}", "error".red().bold(), "Usage: mini-lisp <file>".underline());
-        std::process::exit(1);
-    });
+    let args = parse_args();
+    args.color.apply();
+
+    start(args);
+}
+
+fn start(args: Args) {
+    let Some(path) = args.path else {
+        repl::start(args.options);
+        return;
+    };
 
-    let unparsed = std::fs::read_to_string(path).unwrap_or_else(|_| {
+    let bytes = std::fs::read(&path).unwrap_or_else(|_| {
         eprintln!("{}: Failed to read the file!", "error".red().bold());
         std::process::exit(1);
     });
 
     let writer = std::io::stdout();
-    let program =  parser::parse(&unparsed).unwrap_or_else(|err| {
-        eprintln!("{}:\n{}", "syntax error".red().bold(), err);
+    if bytes.starts_with(bytecode::MAGIC) && args.command == Command::Run {
+        let module = bytecode::decode(&bytes).unwrap_or_else(|err| {
+            report(&path, "", &err);
+            std::process::exit(1);
+        });
+        // Compiled programs always run on the VM.
+        if let Err(err) = vm::run_module(&module, &mut writer.lock(), args.options) {
+            report(&path, &module.source, &err);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let unparsed = String::from_utf8(bytes).unwrap_or_else(|_| {
+        eprintln!("{}: The file is not valid UTF-8!", "error".red().bold());
         std::process::exit(1);
     });
-    
-    if let Err(err) = interpreter::run(program, &mut writer.lock()) {
-        eprintln!("{}: {}", err.0.red().bold(), err.1);
+    let (program, errors) = parser::parse_recovering(&unparsed);
+    if !errors.is_empty() {
+        for err in &errors {
+            report(&path, &unparsed, err);
+        }
+        std::process::exit(1);
+    }
+
+    if args.command == Command::Fmt {
+        let formatted = format::format(&program, &unparsed);
+        if args.check {
+            if formatted != unparsed {
+                eprintln!("{}: '{}' is not formatted", "error".red().bold(), path);
+                std::process::exit(1);
+            }
+            return;
+        }
+        match args.output {
+            Some(output) => write_output(&output, formatted.as_bytes()),
+            None if args.write => write_output(&path, formatted.as_bytes()),
+            None => print!("{}", formatted),
+        }
+        return;
+    }
+
+    if args.check_arity {
+        let errors = check::check_arity(&program);
+        for err in &errors {
+            report(&path, &unparsed, err);
+        }
+        if !errors.is_empty() {
+            std::process::exit(1);
+        }
+    }
+
+    if args.typecheck {
+        let errors = typecheck::typecheck(&program);
+        for err in &errors {
+            report(&path, &unparsed, err);
+        }
+        if !errors.is_empty() {
+            std::process::exit(1);
+        }
+    }
+
+    if args.command != Command::Run {
+        let contents = match args.command {
+            Command::Run | Command::Fmt => unreachable!(),
+            Command::Compile => {
+                let mut program = program;
+                if args.options.optimize {
+                    // The overflow mode is only chosen when the bytecode runs, so only fold
+                    // what gives the same result in every mode.
+                    optimize::optimize(&mut program, OverflowMode::Error);
+                }
+                bytecode::encode(&compiler::compile_program(program, &unparsed))
+            }
+            Command::EmitC | Command::EmitWat => {
+                let source = match args.command {
+                    Command::EmitC => emit_c::emit(program, &path, &args.options),
+                    _ => emit_wat::emit(program, &args.options),
+                };
+                source
+                    .unwrap_or_else(|err| {
+                        report(&path, &unparsed, &err);
+                        std::process::exit(1);
+                    })
+                    .into_bytes()
+            }
+        };
+        let output = args.output.unwrap_or_else(|| {
+            let output = Path::new(&path).with_extension(args.command.extension());
+            output.to_string_lossy().into_owned()
+        });
+        write_output(&output, &contents);
+        return;
+    }
+
+    let result = match args.options.backend {
+        Backend::Tree => interpreter::run_with_options(program, &mut writer.lock(), args.options),
+        Backend::Vm => vm::run_with_options(program, &mut writer.lock(), args.options),
+    };
+    if let Err(err) = result {
+        report(&path, &unparsed, &err);
+        std::process::exit(1);
+    }
+}
+
+fn write_output(output: &str, contents: &[u8]) {
+    if let Err(err) = std::fs::write(output, contents) {
+        eprintln!("{}: cannot write '{}': {}", "error".red().bold(), output, err);
+        std::process::exit(1);
     }
 }
 
+fn report(path: &str, source: &str, err: &Error) {
+    eprint!("{}", Diagnostic::from_error(err).render(path, source));
+}
+
 #[cfg(test)]
 mod tests {
     use std::io;
 
+    use std::io::Write;
+
+    use mini_lisp::ast::{Addr, CommentKind, ExpKind, Program, Span, StmtKind};
+    use mini_lisp::resolve;
+    use mini_lisp::diagnostic;
+
     use super::*;
 
+    /// Runs `program` on both backends, checking that they print and fail alike.
+    fn run_with_options<W: Write>(program: Program, writer: &mut W, options: interpreter::Options) -> Result<(), Error> {
+        let mut tree_output = Vec::new();
+        let tree_result = interpreter::run_with_options(program.clone(), &mut tree_output, options.clone());
+        let mut vm_output = Vec::new();
+        let vm_result = vm::run_with_options(program, &mut vm_output, options);
+        assert_eq!(String::from_utf8_lossy(&tree_output), String::from_utf8_lossy(&vm_output));
+        assert_eq!(format!("{:?}", tree_result), format!("{:?}", vm_result));
+        writer.write_all(&tree_output).unwrap();
+        tree_result
+    }
+
+    fn run<W: Write>(program: Program, writer: &mut W) -> Result<(), Error> {
+        run_with_options(program, writer, interpreter::Options::default())
+    }
+
     #[test]
     fn test_syntax_error() {
         let unparsed = "(+)";
-        let result = parser::parse(&unparsed);
+        let result = parser::parse(unparsed);
         assert!(result.is_err());
 
         let unparsed = "(+ (* 5 2) -)";
-        let result = parser::parse(&unparsed);
+        let result = parser::parse(unparsed);
         assert!(result.is_err());
+
+        let unparsed = "(print-num 1)\n(+ 1)";
+        let result = parser::parse(unparsed);
+        assert!(matches!(result, Err(Error::Syntax { span, .. }) if span.line == 2 && span.col == 5));
+
+        // A file without statements is a program that does nothing.
+        assert!(parser::parse("").unwrap().stmts.is_empty());
+        assert!(parser::parse(" ; nothing\n").unwrap().stmts.is_empty());
+    }
+
+    #[test]
+    fn test_syntax_error_recovery() {
+        let unparsed = r"(print-num 1)
+(print-num (+ 1))
+(define x 2)
+(print-num (* x 3)
+(print-bool #t))
+(- 1)
+(print-num x)";
+        let (program, errors) = parser::parse_recovering(unparsed);
+        let locations: Vec<(usize, usize)> = errors
+            .iter()
+            .map(|err| err.span().map(|span| (span.line, span.col)).unwrap())
+            .collect();
+        assert_eq!(locations, vec![(2, 16), (4, 19), (5, 16), (6, 5)]);
+        assert!(errors.iter().all(|err| matches!(err, Error::Syntax { .. })));
+
+        assert_eq!(program.stmts.len(), 4);
+        assert_eq!(program.stmts[1].span, Span::new(32, 44, 3, 1));
+        let mut writer = Vec::new();
+        run(program, &mut writer).unwrap();
+        assert_eq!(String::from_utf8(writer).unwrap(), "1\n#t\n2\n");
+
+        let (program, errors) = parser::parse_recovering("(print-num 1)");
+        assert_eq!(program.stmts.len(), 1);
+        assert!(errors.is_empty());
+    }
+
+    #[test]
+    fn test_comments() {
+        let unparsed = "; (print-num 0)
+#| outer #| (print-num 0) |# still outer |#
+(define x ; x is 2
+  2)
+#;(define x 3)
+(print-num (+ x #; #; 10 20 1)) ;; prints 3";
+        let program = parser::parse(unparsed).unwrap();
+        let comments: Vec<(CommentKind, &str)> = program.comments.iter().map(|comment| (comment.kind, comment.text.as_str())).collect();
+        assert_eq!(
+            comments,
+            vec![
+                (CommentKind::Line, "; (print-num 0)"),
+                (CommentKind::Block, "#| outer #| (print-num 0) |# still outer |#"),
+                (CommentKind::Line, "; x is 2"),
+                (CommentKind::Datum, "#;(define x 3)"),
+                (CommentKind::Datum, "#; #; 10 20"),
+                (CommentKind::Line, ";; prints 3"),
+            ]
+        );
+        assert_eq!(program.comments[2].span, Span::new(70, 78, 3, 11));
+        let mut writer = Vec::new();
+        run(program, &mut writer).unwrap();
+        assert_eq!(String::from_utf8(writer).unwrap(), "3\n");
+
+        let formatted = format::format(&parser::parse(unparsed).unwrap(), unparsed);
+        assert_eq!(
+            formatted,
+            "; (print-num 0)\n\
+             #| outer #| (print-num 0) |# still outer |#\n\
+             (define x ; x is 2\n  \
+               2)\n\
+             #;(define x 3)\n\
+             (print-num (+ x #; #; 10 20 1)) ;; prints 3\n"
+        );
+
+        let (program, errors) = parser::parse_recovering("(print-num 1) ; one\n(print-num (+ 1))\n#| open\n(print-num 2)");
+        assert_eq!(program.stmts.len(), 1);
+        assert_eq!(program.comments.len(), 1);
+        let messages: Vec<(String, usize)> = errors.iter().map(|err| (err.to_string(), err.span().unwrap().line)).collect();
+        assert_eq!(
+            messages,
+            vec![("syntax error: expected EXP".to_string(), 2), ("syntax error: unclosed block comment".to_string(), 3)]
+        );
+        let (_, errors) = parser::parse_recovering("(print-num 1) #;");
+        assert_eq!(errors.iter().map(|err| err.to_string()).collect::<Vec<_>>(), vec!["syntax error: expected STMT"]);
     }
 
     #[test]
@@ -47,18 +438,18 @@ mod tests {
             (print-num 2)
             (print-num 3)
             (print-num 4)";
-        let program = parser::parse(&unparsed).unwrap();
+        let program = parser::parse(unparsed).unwrap();
         let mut writer = Vec::new();
-        interpreter::run(program, &mut writer).unwrap();
+        run(program, &mut writer).unwrap();
         let output = String::from_utf8(writer).unwrap();
         assert_eq!(output, "1\n2\n3\n4\n");
 
         let unparsed = r"(print-num 0)
             (print-num -123)
             (print-num 456)";
-        let program = parser::parse(&unparsed).unwrap();
+        let program = parser::parse(unparsed).unwrap();
         let mut writer = Vec::new();
-        interpreter::run(program, &mut writer).unwrap();
+        run(program, &mut writer).unwrap();
         let output = String::from_utf8(writer).unwrap();
         assert_eq!(output, "0\n-123\n456\n");
     }
@@ -71,20 +462,74 @@ mod tests {
             (print-num (mod 10 4))
             (print-num (- (+ 1 2) 4))
             (print-num -256)";
-        let program = parser::parse(&unparsed).unwrap();
+        let program = parser::parse(unparsed).unwrap();
         let mut writer = Vec::new();
-        interpreter::run(program, &mut writer).unwrap();
+        run(program, &mut writer).unwrap();
         let output = String::from_utf8(writer).unwrap();
-        assert_eq!(output, "133\n2\n-1\n-256\n");
+        assert_eq!(output, "401/3\n2\n-1\n-256\n");
 
         let unparsed = r"(print-num (mod 10 (+ 1 2)))
             (print-num (* (/ 1 2) 4))
             (print-num (- (+ 1 2 3 (- 4 5) 6 (/ 7 8) (mod 9 10)) 11))";
-        let program = parser::parse(&unparsed).unwrap();
+        let program = parser::parse(unparsed).unwrap();
+        let mut writer = Vec::new();
+        run(program, &mut writer).unwrap();
+        let output = String::from_utf8(writer).unwrap();
+        assert_eq!(output, "1\n2\n79/8\n");
+    }
+
+    #[test]
+    fn test_numeric_tower() {
+        let unparsed = r"(print-num (/ 1 2))
+            (print-num 6/4)
+            (print-num 4/2)
+            (print-num 1e-9)
+            (print-num -2.5E3)
+            (print-num (+ 1/2 1/3))
+            (print-num (+ 1/2 0.25))
+            (print-num (* 2 1.5))
+            (print-num (/ 1.0 3))
+            (print-num (exact->inexact 1/3))
+            (print-num (exact 0.5))
+            (print-num (exact 2.0))
+            (print-num (mod 7/2 1))
+            (print-num (mod 7.5 2))
+            (print-bool (= 1 1.0))
+            (print-bool (= 1/2 0.5 1))
+            (print-bool (= 1 1 2))
+            (print-bool (= 1/2 0.5 2/4))
+            (print-bool (< 1/3 0.34))";
+        let program = parser::parse(unparsed).unwrap();
         let mut writer = Vec::new();
-        interpreter::run(program, &mut writer).unwrap();
+        run(program, &mut writer).unwrap();
         let output = String::from_utf8(writer).unwrap();
-        assert_eq!(output, "1\n0\n9\n");
+        assert_eq!(
+            output,
+            "1/2\n3/2\n2\n1e-9\n-2500.0\n5/6\n0.75\n3.0\n0.3333333333333333\n0.3333333333333333\n1/2\n2\n1/2\n1.5\n#t\n#f\n#f\n#t\n#t\n"
+        );
+
+        let unparsed = "(print-num (exact (/ 1 (- 0.5 0.5))))";
+        let program = parser::parse(unparsed).unwrap();
+        let err = run(program, &mut io::sink()).unwrap_err();
+        assert!(matches!(err, Error::DivisionByZero { .. }));
+
+        let unparsed = "(print-num (exact (* 1e308 10)))";
+        let program = parser::parse(unparsed).unwrap();
+        let err = run(program, &mut io::sink()).unwrap_err();
+        assert!(matches!(err, Error::NoExactValue { .. }));
+
+        assert!(parser::parse("(print-num 1/0)").is_err());
+        assert!(parser::parse("(print-num 1.)").is_err());
+
+        // The keywords do not swallow the start of longer names.
+        let unparsed = r"(define exactly (fun () 1))
+            (define exact-half (fun (x) (/ x 2)))
+            (print-num (exactly))
+            (print-num (exact-half (exact 3.0)))";
+        let program = parser::parse(unparsed).unwrap();
+        let mut writer = Vec::new();
+        run(program, &mut writer).unwrap();
+        assert_eq!(String::from_utf8(writer).unwrap(), "1\n3/2\n");
     }
 
     #[test]
@@ -97,18 +542,18 @@ mod tests {
             (print-bool (or #f #f))
             (print-bool (not #t))
             (print-bool (not #f))";
-        let program = parser::parse(&unparsed).unwrap();
+        let program = parser::parse(unparsed).unwrap();
         let mut writer = Vec::new();
-        interpreter::run(program, &mut writer).unwrap();
+        run(program, &mut writer).unwrap();
         let output = String::from_utf8(writer).unwrap();
         assert_eq!(output, "#t\n#f\n#f\n#t\n#t\n#f\n#f\n#t\n");
 
         let unparsed = r"(print-bool (or #t #t #f))
             (print-bool (or #f (and #f #t) (not #f)))
             (print-bool (and #t (not #f) (or #f #t) (and #t (not #t))))";
-        let program = parser::parse(&unparsed).unwrap();
+        let program = parser::parse(unparsed).unwrap();
         let mut writer = Vec::new();
-        interpreter::run(program, &mut writer).unwrap();
+        run(program, &mut writer).unwrap();
         let output = String::from_utf8(writer).unwrap();
         assert_eq!(output, "#t\n#t\n#f\n");
     }
@@ -117,9 +562,9 @@ mod tests {
     fn test_if_statement() {
         let unparsed = r"(print-num (if #t 1 2))
             (print-num (if #f 1 2))";
-        let program = parser::parse(&unparsed).unwrap();
+        let program = parser::parse(unparsed).unwrap();
         let mut writer = Vec::new();
-        interpreter::run(program, &mut writer).unwrap();
+        run(program, &mut writer).unwrap();
         let output = String::from_utf8(writer).unwrap();
         assert_eq!(output, "1\n2\n");
 
@@ -127,9 +572,9 @@ mod tests {
             (print-num (if (= 9 (* 2 5))
               0
               (if #t 1 2)))";
-        let program = parser::parse(&unparsed).unwrap();
+        let program = parser::parse(unparsed).unwrap();
         let mut writer = Vec::new();
-        interpreter::run(program, &mut writer).unwrap();
+        run(program, &mut writer).unwrap();
         let output = String::from_utf8(writer).unwrap();
         assert_eq!(output, "6\n1\n");
     }
@@ -140,18 +585,18 @@ mod tests {
             (print-num x)
             (define y (+ 1 2 3))
             (print-num y)";
-        let program = parser::parse(&unparsed).unwrap();
+        let program = parser::parse(unparsed).unwrap();
         let mut writer = Vec::new();
-        interpreter::run(program, &mut writer).unwrap();
+        run(program, &mut writer).unwrap();
         let output = String::from_utf8(writer).unwrap();
         assert_eq!(output, "1\n6\n");
 
         let unparsed = r"(define a (* 1 2 3 4))
             (define b (+ 10 -5 -2 -1))
             (print-num (+ a b))";
-        let program = parser::parse(&unparsed).unwrap();
+        let program = parser::parse(unparsed).unwrap();
         let mut writer = Vec::new();
-        interpreter::run(program, &mut writer).unwrap();
+        run(program, &mut writer).unwrap();
         let output = String::from_utf8(writer).unwrap();
         assert_eq!(output, "26\n");
     }
@@ -162,9 +607,9 @@ mod tests {
               ((fun (x) (+ x 1)) 3))
             (print-num
               ((fun (a b) (+ a b)) 4 5))";
-        let program = parser::parse(&unparsed).unwrap();
+        let program = parser::parse(unparsed).unwrap();
         let mut writer = Vec::new();
-        interpreter::run(program, &mut writer).unwrap();
+        run(program, &mut writer).unwrap();
         let output = String::from_utf8(writer).unwrap();
         assert_eq!(output, "4\n9\n");
 
@@ -172,9 +617,9 @@ mod tests {
             (print-num
               ((fun (x y z) (+ x (* y z))) 10 20 30))
             (print-num x)";
-        let program = parser::parse(&unparsed).unwrap();
+        let program = parser::parse(unparsed).unwrap();
         let mut writer = Vec::new();
-        interpreter::run(program, &mut writer).unwrap();
+        run(program, &mut writer).unwrap();
         let output = String::from_utf8(writer).unwrap();
         assert_eq!(output, "610\n0\n");
     }
@@ -184,18 +629,18 @@ mod tests {
         let unparsed = r"(define foo
               (fun (a b c) (+ a b (* b c))))
             (print-num (foo 10 9 8))";
-        let program = parser::parse(&unparsed).unwrap();
+        let program = parser::parse(unparsed).unwrap();
         let mut writer = Vec::new();
-        interpreter::run(program, &mut writer).unwrap();
+        run(program, &mut writer).unwrap();
         let output = String::from_utf8(writer).unwrap();
         assert_eq!(output, "91\n");
 
         let unparsed = r"(define bar (fun (x) (+ x 1)))
             (define bar-z (fun () 2))
             (print-num (bar (bar-z)))";
-        let program = parser::parse(&unparsed).unwrap();
+        let program = parser::parse(unparsed).unwrap();
         let mut writer = Vec::new();
-        interpreter::run(program, &mut writer).unwrap();
+        run(program, &mut writer).unwrap();
         let output = String::from_utf8(writer).unwrap();
         assert_eq!(output, "3\n");
     }
@@ -220,9 +665,9 @@ mod tests {
             (print-num (fib 5))
             (print-num (fib 10))
             (print-num (fib 20))";
-        let program = parser::parse(&unparsed).unwrap();
+        let program = parser::parse(unparsed).unwrap();
         let mut writer = Vec::new();
-        interpreter::run(program, &mut writer).unwrap();
+        run(program, &mut writer).unwrap();
         let output = String::from_utf8(writer).unwrap();
         assert_eq!(output, "2\n6\n24\n3628800\n1\n2\n5\n55\n6765\n");
 
@@ -245,35 +690,494 @@ mod tests {
             (print-num (gcd 1234 5678))
 
             (print-num (gcd 81 54))";
-        let program = parser::parse(&unparsed).unwrap();
+        let program = parser::parse(unparsed).unwrap();
         let mut writer = Vec::new();
-        interpreter::run(program, &mut writer).unwrap();
+        run(program, &mut writer).unwrap();
         let output = String::from_utf8(writer).unwrap();
         assert_eq!(output, "4\n2\n27\n");
     }
 
+    #[test]
+    fn test_tail_calls() {
+        let unparsed = r"(define loop (fun (n) (if (= n 0) 0 (loop (- n 1)))))
+            (print-num (loop 100000))
+
+            (define is-even (fun (n) (if (= n 0) #t (is-odd (- n 1)))))
+            (define is-odd (fun (n) (and (not (= n 0)) (is-even (- n 1)))))
+            (print-bool (is-even 100000))
+            (print-bool (is-odd 100001))
+
+            (define count (fun (n acc) (or (= n 0) (count (- n 1) (+ acc 1)))))
+            (print-bool (count 100000 0))";
+        // Tail calls must not grow the native stack, so a small one is enough.
+        let output = std::thread::Builder::new()
+            .stack_size(256 * 1024)
+            .spawn(move || {
+                let program = parser::parse(unparsed).unwrap();
+                let mut writer = Vec::new();
+                run(program, &mut writer).unwrap();
+                String::from_utf8(writer).unwrap()
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+        assert_eq!(output, "0\n#t\n#t\n#t\n");
+
+        let unparsed = r"(print-bool (or #t 1))
+            (print-bool (and #f (+ 1 #t)))";
+        let program = parser::parse(unparsed).unwrap();
+        let mut writer = Vec::new();
+        run(program, &mut writer).unwrap();
+        assert_eq!(String::from_utf8(writer).unwrap(), "#t\n#f\n");
+
+        let unparsed = "(define f (fun (n) (if (= n 0) 1 (f (- n 1)))))
+            (print-bool (and #t (f 10)))";
+        let program = parser::parse(unparsed).unwrap();
+        let err = run(program, &mut io::sink()).unwrap_err();
+        assert!(matches!(err, Error::Type { expected: "boolean", found: "number", .. }));
+    }
+
+    #[test]
+    fn test_recursion_limit() {
+        let unparsed = r"(define f (fun (n) (if (= n 0) 0 (+ 1 (f (- n 1))))))
+            (print-num (f 50))
+            (print-num (f 100))";
+        let program = parser::parse(unparsed).unwrap();
+        let options = interpreter::Options {
+            max_depth: 64,
+            ..Default::default()
+        };
+        let mut writer = Vec::new();
+        let err = run_with_options(program, &mut writer, options).unwrap_err();
+        assert_eq!(String::from_utf8(writer).unwrap(), "50\n");
+        let Error::RecursionLimit { limit, backtrace, span } = &err else {
+            panic!("unexpected error: {:?}", err);
+        };
+        assert_eq!(*limit, 64);
+        assert_eq!(backtrace.len(), 5);
+        assert!(backtrace.iter().all(|frame| frame.name.as_deref() == Some("f") && frame.span == *span));
+        assert_eq!((span.line, span.col), (1, 39));
+        assert_eq!(err.message(), "maximum recursion depth of 64 exceeded");
+
+        // Tail calls reuse their frame, so they never hit the limit.
+        let unparsed = r"(define loop (fun (n) (if (= n 0) 0 (loop (- n 1)))))
+            (print-num (loop 1000))";
+        let program = parser::parse(unparsed).unwrap();
+        let options = interpreter::Options {
+            max_depth: 2,
+            ..Default::default()
+        };
+        let mut writer = Vec::new();
+        run_with_options(program, &mut writer, options).unwrap();
+        assert_eq!(String::from_utf8(writer).unwrap(), "0\n");
+
+        // The default limit is reached before the native stack of any thread runs out.
+        let unparsed = r"(define f (fun (n) (if (= n 0) 0 (+ 1 (f (- n 1))))))
+            (print-num (f 999))
+            (print-num (f 1000))";
+        let (output, err) = std::thread::spawn(move || {
+            let program = parser::parse(unparsed).unwrap();
+            let mut writer = Vec::new();
+            let err = run(program, &mut writer).unwrap_err();
+            (String::from_utf8(writer).unwrap(), err)
+        })
+        .join()
+        .unwrap();
+        assert_eq!(output, "999\n");
+        assert!(matches!(err, Error::RecursionLimit { limit: 1000, .. }));
+    }
+
     #[test]
     fn test_type_checking() {
         let unparsed = r"(+ 1 2 3 (or #t #f))";
-        let program = parser::parse(&unparsed).unwrap();
-        let result = interpreter::run(program, &mut io::stdout());
+        let program = parser::parse(unparsed).unwrap();
+        let result = run(program, &mut io::stdout());
         assert!(result.is_err());
         if let Err(err) = result {
-            assert_eq!(err, ("type error".to_string(), "expect 'number' but got 'boolean'".to_string()));
+            assert!(matches!(
+                err,
+                Error::Type { expected: "number", found: "boolean", span, .. } if span == Span::new(9, 19, 1, 10)
+            ));
+            assert_eq!(err.to_string(), "type error: `+` argument 4: expected number, got boolean");
         }
 
         let unparsed = r"(define f
               (fun (x)
                 (if (> x 10) 10 (= x 5))))
             (print-num (* 2 (f 4)))";
-        let program = parser::parse(&unparsed).unwrap();
-        let result = interpreter::run(program, &mut io::stdout());
+        let program = parser::parse(unparsed).unwrap();
+        let result = run(program, &mut io::stdout());
         assert!(result.is_err());
         if let Err(err) = result {
-            assert_eq!(err, ("type error".to_string(), "expect 'number' but got 'boolean'".to_string()));
+            assert!(matches!(err, Error::Type { expected: "number", found: "boolean", .. }));
+            assert_eq!(err.span().map(|span| (span.line, span.col)), Some((4, 29)));
+            assert_eq!(err.message(), "`*` argument 2: expected number, got boolean");
+        }
+
+        let programs = [
+            ("(+ 1 2 3 (fun (x) x))", "`+` argument 4: expected number, got function"),
+            ("(if (fun () #t) 1 2)", "`if` argument 1: expected boolean, got function"),
+            ("(not 0)", "`not` argument 1: expected boolean, got number"),
+            ("(or #f 1 #t)", "`or` argument 2: expected boolean, got number"),
+            ("(print-bool (exact->inexact 1))", "`print-bool` argument 1: expected boolean, got number"),
+            ("(define f (fun (n) n)) (and #t (f 10))", "`and` argument 2: expected boolean, got number"),
+        ];
+        for (unparsed, message) in programs {
+            let program = parser::parse(unparsed).unwrap();
+            let err = run(program, &mut io::sink()).unwrap_err();
+            assert_eq!(err.message(), message);
         }
     }
 
+    #[test]
+    fn test_spans() {
+        let unparsed = "(define x 1)\n(print-num\n  (+ x y))";
+        let program = parser::parse(unparsed).unwrap();
+        assert_eq!(program.stmts[0].span, Span::new(0, 12, 1, 1));
+        assert_eq!(program.stmts[1].span, Span::new(13, 34, 2, 1));
+
+        let result = run(program, &mut io::stdout());
+        assert!(matches!(
+            result.unwrap_err(),
+            Error::UnboundVariable { name, span, .. } if name == "y" && span == Span::new(31, 32, 3, 8)
+        ));
+    }
+
+    #[test]
+    fn test_display() {
+        let unparsed = "(define  fact\n  (fun (n)\n    (define one 1)\n    (if (< n 2) one (* n (fact (- n one))))))\n\
+            (print-num (fact 5))   (print-bool (and #t (or #f (not (= 1 2)))))\n\
+            ((fun () (exact->inexact (/ 1/2 (mod -7 3)))))  (exact 2.5e-3)";
+        let program = parser::parse(unparsed).unwrap();
+        let printed = program.to_string();
+        assert_eq!(
+            printed,
+            "(define fact (fun (n) (define one 1) (if (< n 2) one (* n (fact (- n one))))))\n\
+             (print-num (fact 5))\n\
+             (print-bool (and #t (or #f (not (= 1 2)))))\n\
+             ((fun () (exact->inexact (/ 1/2 (mod -7 3)))))\n\
+             (exact 0.0025)\n"
+        );
+        assert_eq!(parser::parse(&printed).unwrap().to_string(), printed);
+    }
+
+    #[test]
+    fn test_format() {
+        let unparsed = "(define  fib (fun (n) (define helper (fun (a b k) (if (= k 0) a (helper b (+ a b) (- k 1))))) (helper 0 1 n)))\n\n\n\
+            (print-num (+ (fib 10) (fib 20) (fib 30) (fib 40) (fib 50) (fib 60) (fib 70) 1.50))";
+        let formatted = format::format(&parser::parse(unparsed).unwrap(), unparsed);
+        assert_eq!(
+            formatted,
+            "(define fib\n  \
+               (fun (n)\n    \
+                 (define helper (fun (a b k) (if (= k 0) a (helper b (+ a b) (- k 1)))))\n    \
+                 (helper 0 1 n)))\n\
+             \n\
+             (print-num (+ (fib 10)\n              \
+                           (fib 20)\n              \
+                           (fib 30)\n              \
+                           (fib 40)\n              \
+                           (fib 50)\n              \
+                           (fib 60)\n              \
+                           (fib 70)\n              \
+                           1.50))\n"
+        );
+
+        // Formatting random programs keeps their meaning and comments and is idempotent.
+        let mut seed = 0x2545f4914f6cdd1d_u64;
+        let mut random = move |bound: usize| {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            (seed % bound as u64) as usize
+        };
+        fn exp(depth: usize, random: &mut dyn FnMut(usize) -> usize) -> String {
+            let space = |random: &mut dyn FnMut(usize) -> usize| {
+                let comments = [" ; note (\n", " #| block |# ", "\n#| two\n  lines |#\n", " #;(skipped 1) "];
+                if random(4) == 0 { comments[random(4)] } else { [" ", "  ", "\n ", "\n\n\t"][random(4)] }
+            };
+            let list = |head: &str, count: usize, random: &mut dyn FnMut(usize) -> usize| {
+                let mut out = format!("({}", head);
+                for _ in 0..count {
+                    out.push_str(space(random));
+                    out.push_str(&exp(depth - 1, random));
+                }
+                out + ")"
+            };
+            if depth == 0 {
+                return ["0", "-12", "3/4", "2.50", "#t", "#f", "x", "long-variable-name"][random(8)].to_string();
+            }
+            match random(8) {
+                0 => list(["+", "*", "=", "and", "or"][random(5)], 2 + random(6), random),
+                1 => list(["-", "/", "mod", ">", "<"][random(5)], 2, random),
+                2 => list(["not", "exact", "exact->inexact"][random(3)], 1, random),
+                3 => list("if", 3, random),
+                4 => {
+                    let body = exp(depth - 1, random);
+                    format!("(fun (x{}y){}(define z {}){}{})", space(random), space(random), exp(depth - 1, random), space(random), body)
+                }
+                5 => list("f", random(4), random),
+                6 => list(["fun ()", "fun (x)"][random(2)], 1, random),
+                _ => exp(0, random),
+            }
+        }
+        for _ in 0..200 {
+            let mut unparsed = String::new();
+            for _ in 0..1 + random(4) {
+                let stmt = exp(1 + random(5), &mut random);
+                let stmt = match random(3) {
+                    0 => format!("(define f {})", stmt),
+                    1 => format!("(print-num {})", stmt),
+                    _ => stmt,
+                };
+                unparsed.push_str(&stmt);
+                unparsed.push_str(["\n", "\n\n\n", " ", " ; top\n", "\n#;(define f 1)\n"][random(5)]);
+            }
+            let program = parser::parse(&unparsed).unwrap();
+            let formatted = format::format(&program, &unparsed);
+            let reparsed = parser::parse(&formatted).unwrap();
+            assert_eq!(reparsed.to_string(), program.to_string(), "{}", unparsed);
+            let texts = |program: &Program| program.comments.iter().map(|comment| comment.text.clone()).collect::<Vec<_>>();
+            assert_eq!(texts(&reparsed), texts(&program), "{}", unparsed);
+            assert_eq!(format::format(&reparsed, &formatted), formatted, "{}", unparsed);
+        }
+    }
+
+    #[test]
+    fn test_diagnostics() {
+        colored::control::set_override(false);
+
+        let unparsed = "(define fib (fun (n) n))\n(print-num\n  (+ 1 (fob 2)))";
+        let program = parser::parse(unparsed).unwrap();
+        let err = run(program, &mut io::sink()).unwrap_err();
+        assert_eq!(
+            Diagnostic::from_error(&err).render("main.lsp", unparsed),
+            "unbound variable: variable 'fob' not found\n \
+             --> main.lsp:3:9\n  \
+             |\n\
+             3 |   (+ 1 (fob 2)))\n  \
+             |         ^^^ not found in this scope\n  \
+             |\n  \
+             = help: did you mean `fib`?\n"
+        );
+
+        let unparsed = "(print-num (+ 1 #t))";
+        let program = parser::parse(unparsed).unwrap();
+        let err = run(program, &mut io::sink()).unwrap_err();
+        assert_eq!(
+            Diagnostic::from_error(&err).render("main.lsp", unparsed),
+            "type error: `+` argument 2: expected number, got boolean\n \
+             --> main.lsp:1:17\n  \
+             |\n\
+             1 | (print-num (+ 1 #t))\n  \
+             |                 ^^ this is a boolean, not a number\n"
+        );
+
+        assert_eq!(diagnostic::suggest("fob", ["fib", "foo-bar", "x"]), Some("fib".to_string()));
+        assert_eq!(diagnostic::suggest("y", ["fib", "abc"]), None);
+    }
+
+    #[test]
+    fn test_arity() {
+        let unparsed = r"(define foo (fun (a b c) (+ a b c)))
+            (print-num (foo 1))";
+        let program = parser::parse(unparsed).unwrap();
+        let err = run(program, &mut io::sink()).unwrap_err();
+        assert!(matches!(
+            &err,
+            Error::Arity { name: Some(name), expected: 3, found: 1, .. } if name == "foo"
+        ));
+        assert_eq!(err.to_string(), "arity error: function 'foo' expects 3 arguments but got 1");
+
+        let unparsed = r"(print-num ((fun (x) x) 1 2))";
+        let program = parser::parse(unparsed).unwrap();
+        let err = run(program, &mut io::sink()).unwrap_err();
+        assert!(matches!(err, Error::Arity { name: None, expected: 1, found: 2, .. }));
+
+        let unparsed = r"(define foo (fun (a b) (+ a b)))
+            (define bar (fun (foo) (foo 1)))
+            (define baz (fun (x) (foo x)))
+            (define maybe (fun (x) x))
+            (define maybe (fun () 1))
+            (maybe 1)
+            (if #f (foo 1 2 3) ((fun () 0) 4))";
+        let program = parser::parse(unparsed).unwrap();
+        let errors = check::check_arity(&program);
+        let found: Vec<(usize, usize)> = errors
+            .iter()
+            .map(|err| match err {
+                Error::Arity { expected, found, .. } => (*expected, *found),
+                _ => unreachable!(),
+            })
+            .collect();
+        assert_eq!(found, vec![(2, 1), (2, 3), (0, 1)]);
+        assert_eq!(errors[0].span().unwrap().line, 3);
+    }
+
+    #[test]
+    fn test_typecheck() {
+        let unparsed = r"(define id (fun (x) x))
+            (define twice (fun (f x) (f (f x))))
+            (define fact (fun (n) (if (< n 2) 1 (* n (fact (- n 1))))))
+            (define is-even (fun (n) (if (= n 0) #t (is-odd (- n 1)))))
+            (define is-odd (fun (n) (if (= n 0) #f (is-even (- n 1)))))
+            (define counter (fun (n)
+                (define step (fun (k) (+ k n)))
+                (twice step 0)))
+            (print-num (id (fact 5)))
+            (print-bool (id (is-even 10)))
+            (print-num (twice (fun (x) (* x 2)) (counter 3)))
+            (print-bool (twice (fun (b) (not b)) #t))";
+        let errors = typecheck::typecheck(&parser::parse(unparsed).unwrap());
+        assert!(errors.is_empty(), "{:?}", errors);
+
+        let unparsed = "(print-num (+ 1 (or #t #f)))";
+        let errors = typecheck::typecheck(&parser::parse(unparsed).unwrap());
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].to_string(), "type error: expected type 'number' but found 'boolean'");
+        assert_eq!(errors[0].span(), Some(Span::new(16, 26, 1, 17)));
+
+        let unparsed = r"(print-num 1)
+            (define add (fun (a b) (+ a b)))
+            (print-bool (add 1 2))
+            (add 1 #t)
+            (add 1)
+            (if 1 2 #f)
+            (define one 1) (one 2)
+            (define loop (fun (f) (f f)))
+            (print-num undefined)";
+        let errors = typecheck::typecheck(&parser::parse(unparsed).unwrap());
+        let found: Vec<(usize, String)> = errors.iter().map(|err| (err.span().unwrap().line, err.message())).collect();
+        assert_eq!(
+            found,
+            vec![
+                (3, "expected type 'boolean' but found 'number'".to_string()),
+                (4, "expected type 'number' but found 'boolean'".to_string()),
+                (5, "function 'add' expects 2 arguments but got 1".to_string()),
+                (6, "expected type 'boolean' but found 'number'".to_string()),
+                (6, "expected type 'number' but found 'boolean'".to_string()),
+                (7, "'number' is not callable".to_string()),
+                (8, "infinite type: 'a' would have to be '(a -> b)'".to_string()),
+                (9, "variable 'undefined' not found".to_string()),
+            ]
+        );
+
+        let params: Vec<String> = (0..28).map(|i| format!("p{}", i)).collect();
+        let unparsed = format!("(print-num (fun ({}) p0))", params.join(" "));
+        let errors = typecheck::typecheck(&parser::parse(&unparsed).unwrap());
+        assert_eq!(
+            errors[0].message(),
+            "expected type 'number' but found '(a b c d e f g h i j k l m n o p q r s t u v w x y z a1 b1 -> a)'"
+        );
+    }
+
+    #[test]
+    fn test_not_callable() {
+        let unparsed = r"(define x 1)
+            (x 2)";
+        let program = parser::parse(unparsed).unwrap();
+        let err = run(program, &mut io::sink()).unwrap_err();
+        assert!(matches!(err, Error::NotCallable { found: "number", span } if span.line == 2));
+        assert_eq!(err.to_string(), "type error: 'number' is not callable");
+
+        let unparsed = r"(define f (fun (g) (g)))
+            (f #t)";
+        let program = parser::parse(unparsed).unwrap();
+        let err = run(program, &mut io::sink()).unwrap_err();
+        assert!(matches!(err, Error::NotCallable { found: "boolean", .. }));
+    }
+
+    #[test]
+    fn test_no_panic() {
+        let programs = [
+            "(define x 1) (x)",
+            "(define b #f) (print-num (b 1 2 3))",
+            "((fun (f) (f 1)) 2)",
+            "((fun (f) (f)) (fun (x) x))",
+            "(print-num (fun (x) x))",
+            "(print-bool 1)",
+            "(if 1 2 3)",
+            "(not 0)",
+            "(+ (fun () 1) 2)",
+            "(and #t (fun () #t))",
+            "(print-num undefined)",
+            "(print-num (/ 1 0))",
+            "(print-num (mod 7 (- 3 3)))",
+            "(print-num (* 4611686018427387904 2))",
+            "(print-num (+ 9223372036854775807 1))",
+            "(print-num (- -9223372036854775807 2))",
+            "(print-num (/ (- -9223372036854775807 1) -1))",
+            "(print-num (mod (- -9223372036854775807 1) -1))",
+            "(print-num (/ 123456789012345678901234567890 0))",
+        ];
+        for unparsed in programs {
+            let result = std::panic::catch_unwind(|| {
+                let program = parser::parse(unparsed).unwrap();
+                let _ = run(program, &mut io::sink());
+            });
+            assert!(result.is_ok(), "interpreter panicked on {:?}", unparsed);
+        }
+    }
+
+    #[test]
+    fn test_checked_arithmetic() {
+        let unparsed = "(print-num (+ 1 (/ 10 (- 2 2))))";
+        let program = parser::parse(unparsed).unwrap();
+        let err = run(program, &mut io::sink()).unwrap_err();
+        assert!(matches!(err, Error::DivisionByZero { span } if span == Span::new(16, 30, 1, 17)));
+
+        let unparsed = "(print-num (mod 10 0))";
+        let program = parser::parse(unparsed).unwrap();
+        let err = run(program, &mut io::sink()).unwrap_err();
+        assert!(matches!(err, Error::DivisionByZero { .. }));
+        assert_eq!(err.to_string(), "arithmetic error: division by zero");
+
+        let unparsed = r"(define big 9223372036854775807)
+            (print-num (+ big 1))
+            (print-num (* big -2))
+            (print-num (- (- 0 big) 2))";
+        let mut outputs = Vec::new();
+        for mode in [OverflowMode::Error, OverflowMode::Wrap, OverflowMode::default()] {
+            let program = parser::parse(unparsed).unwrap();
+            let mut writer = Vec::new();
+            let options = interpreter::Options {
+                overflow: mode,
+                ..Default::default()
+            };
+            let result = run_with_options(program, &mut writer, options);
+            outputs.push((String::from_utf8(writer).unwrap(), result));
+        }
+        assert_eq!(outputs[0].0, "");
+        assert!(matches!(outputs[0].1, Err(Error::Overflow { span }) if span.line == 2));
+        assert_eq!(outputs[1].0, "-9223372036854775808\n2\n9223372036854775807\n");
+        assert_eq!(
+            outputs[2].0,
+            "9223372036854775808\n-18446744073709551614\n-9223372036854775809\n"
+        );
+    }
+
+    #[test]
+    fn test_bignum() {
+        let unparsed = r"(define fact
+              (fun (n) (if (< n 2) 1 (* n (fact (- n 1))))))
+            (print-num (fact 25))
+            (print-num 123456789012345678901234567890)
+            (print-num (- 123456789012345678901234567890 123456789012345678901234567889))
+            (print-num (/ (fact 30) (fact 28)))
+            (print-num (mod (fact 25) 1000000007))
+            (print-bool (> (fact 21) 9223372036854775807))
+            (print-bool (< -99999999999999999999 -9223372036854775808))
+            (print-bool (= (* (fact 20) 21) (fact 21)))";
+        let program = parser::parse(unparsed).unwrap();
+        let mut writer = Vec::new();
+        run(program, &mut writer).unwrap();
+        let output = String::from_utf8(writer).unwrap();
+        assert_eq!(
+            output,
+            "15511210043330985984000000\n123456789012345678901234567890\n1\n870\n440732388\n#t\n#t\n#t\n"
+        );
+    }
+
     #[test]
     fn test_nested_function() {
         let unparsed = r"(define dist-square
@@ -281,9 +1185,9 @@ mod tests {
                 (define square (fun (x) (* x x)))
                 (+ (square x) (square y))))
             (print-num (dist-square 3 4))";
-        let program = parser::parse(&unparsed).unwrap();
+        let program = parser::parse(unparsed).unwrap();
         let mut writer = Vec::new();
-        interpreter::run(program, &mut writer).unwrap();
+        run(program, &mut writer).unwrap();
         let output = String::from_utf8(writer).unwrap();
         assert_eq!(output, "25\n");
 
@@ -295,11 +1199,234 @@ mod tests {
                   (abs (- a b))))
             (print-num (diff 1 10))
             (print-num (diff 10 2))";
-        let program = parser::parse(&unparsed).unwrap();
+        let program = parser::parse(unparsed).unwrap();
         let mut writer = Vec::new();
-        interpreter::run(program, &mut writer).unwrap();
+        run(program, &mut writer).unwrap();
         let output = String::from_utf8(writer).unwrap();
         assert_eq!(output, "9\n8\n");
+
+        let unparsed = r"(define double-plus
+              (fun (x)
+                (define y (* x 2))
+                (define z (+ y 1))
+                z))
+            (print-num (double-plus 1))
+            (print-num (double-plus 5))
+
+            (define parity
+              (fun (n)
+                (define is-even (fun (n) (if (= n 0) #t (is-odd (- n 1)))))
+                (define is-odd (fun (n) (if (= n 0) #f (is-even (- n 1)))))
+                (is-even n)))
+            (print-bool (parity 10))
+            (print-bool (parity 7))
+
+            (define make-adder
+              (fun (x)
+                (define add (fun (y) (+ x y)))
+                add))
+            (define add-one (make-adder 1))
+            (define add-ten (make-adder 10))
+            (print-num (add-one 1))
+            (print-num (add-ten 1))";
+        let program = parser::parse(unparsed).unwrap();
+        let mut writer = Vec::new();
+        run(program, &mut writer).unwrap();
+        let output = String::from_utf8(writer).unwrap();
+        assert_eq!(output, "3\n11\n#t\n#f\n2\n11\n");
+
+        let unparsed = r"(define f
+              (fun (x)
+                (define y z)
+                (define z 1)
+                y))
+            (f 1)";
+        let program = parser::parse(unparsed).unwrap();
+        let err = run(program, &mut io::sink()).unwrap_err();
+        assert!(matches!(err, Error::UnboundVariable { name, .. } if name == "z"));
+    }
+
+    #[test]
+    fn test_resolve() {
+        let unparsed = r"(define x 1)
+            (define f
+              (fun (x y)
+                (define z (+ x y))
+                (fun (w) (+ w x z g))))";
+        let mut program = parser::parse(unparsed).unwrap();
+        let mut globals = mini_lisp::env::Env::new();
+        resolve::resolve(&mut program, &mut globals);
+
+        let StmtKind::DefStmt { id, exp } = &program.stmts[1].kind else { unreachable!() };
+        assert!(matches!(id.kind, ExpKind::Var { addr: Addr::Global(1), .. }));
+        let ExpKind::FunExp(lambda) = &exp.kind else { unreachable!() };
+        assert_eq!(*lambda.slots, ["x", "y", "z"]);
+        let StmtKind::DefStmt { id, .. } = &lambda.def_stmts[0].kind else { unreachable!() };
+        assert!(matches!(id.kind, ExpKind::Var { addr: Addr::Local { depth: 0, slot: 2 }, .. }));
+        let ExpKind::FunExp(lambda) = &lambda.body.kind else { unreachable!() };
+        let ExpKind::NumExp { args, .. } = &lambda.body.kind else { unreachable!() };
+        let addrs: Vec<Addr> = args
+            .iter()
+            .map(|arg| match arg.kind {
+                ExpKind::Var { addr, .. } => addr,
+                _ => unreachable!(),
+            })
+            .collect();
+        assert_eq!(
+            addrs,
+            vec![
+                Addr::Local { depth: 0, slot: 0 },
+                Addr::Local { depth: 1, slot: 0 },
+                Addr::Local { depth: 1, slot: 2 },
+                Addr::Global(2),
+            ]
+        );
+
+        let unparsed = r"(define x 1)
+            (define f (fun (x) (define x (* x 10)) x))
+            (define g (fun () x))
+            (print-num (f 2))
+            (print-num (g))
+            (define x 3)
+            (print-num (g))";
+        let program = parser::parse(unparsed).unwrap();
+        let mut writer = Vec::new();
+        run(program, &mut writer).unwrap();
+        assert_eq!(String::from_utf8(writer).unwrap(), "20\n1\n3\n");
+    }
+
+    #[test]
+    fn test_compiler() {
+        let unparsed = "(define f (fun (n) (if (= n 0) 0 (+ 1 (f (- n 1))))))
+            (define g (fun (n) (if (= n 0) 0 (g (- n 1)))))";
+        let mut program = parser::parse(unparsed).unwrap();
+        resolve::resolve(&mut program, &mut mini_lisp::env::Env::new());
+        let calls: Vec<(bool, bool)> = program
+            .stmts
+            .iter()
+            .map(|stmt| {
+                let proto = &compiler::compile_stmt(stmt).protos[0];
+                (
+                    proto.code.iter().any(|op| matches!(op, compiler::Op::Call(_))),
+                    proto.code.iter().any(|op| matches!(op, compiler::Op::TailCall(_))),
+                )
+            })
+            .collect();
+        assert_eq!(calls, vec![(true, false), (false, true)]);
+    }
+
+    #[test]
+    fn test_bytecode() {
+        let unparsed = "(define make-adder (fun (x) (define y (* x 2)) (fun (z) (+ y z))))
+            (define add (make-adder 3))
+            (print-num (add 4))
+            (print-num (/ 1 3))
+            (print-num (exact->inexact 1))
+            (print-bool (and #t (= (add 0) 6)))
+            (print-num (add #t))";
+        let program = parser::parse(unparsed).unwrap();
+        let module = compiler::compile_program(program, unparsed);
+        let bytes = bytecode::encode(&module);
+        assert!(bytes.starts_with(bytecode::MAGIC));
+        let decoded = bytecode::decode(&bytes).unwrap();
+        assert_eq!(decoded, module);
+
+        let mut writer = Vec::new();
+        let result = vm::run_module(&decoded, &mut writer, interpreter::Options::default());
+        assert_eq!(String::from_utf8(writer).unwrap(), "10\n1/3\n1.0\n#t\n");
+        assert!(matches!(result, Err(Error::Type { span, .. }) if span.line == 1 && span.col == 62));
+
+        let message = |bytes: &[u8]| match bytecode::decode(bytes) {
+            Err(Error::Bytecode { message }) => message,
+            result => panic!("expected a bytecode error, got {:?}", result),
+        };
+        let mut newer = bytes.clone();
+        newer[4..6].copy_from_slice(&(bytecode::VERSION + 1).to_le_bytes());
+        assert_eq!(
+            message(&newer),
+            format!("bytecode version {} is not supported (expected {}); recompile the source", bytecode::VERSION + 1, bytecode::VERSION)
+        );
+        let mut flipped = bytes.clone();
+        let last = flipped.len() - 1;
+        flipped[last] ^= 1;
+        assert_eq!(message(&flipped), "checksum mismatch, the file is corrupt");
+        assert_eq!(message(&bytes[..bytes.len() / 2]), "checksum mismatch, the file is corrupt");
+        assert_eq!(message(&bytes[..8]), "the file is truncated or corrupt");
+        assert_eq!(message(b"(print-num 1)"), "not a mini-lisp bytecode file");
+
+        // Tampered code with a valid checksum fails instead of panicking the VM.
+        let program = parser::parse("(print-num 1)").unwrap();
+        let module = compiler::compile_program(program, "(print-num 1)");
+        for (from, to) in [(compiler::Op::PrintNum, compiler::Op::Not), (compiler::Op::Const(0), compiler::Op::PrintNum)] {
+            let mut tampered = module.clone();
+            let proto = std::rc::Rc::make_mut(&mut tampered.stmts[0]);
+            let index = proto.code.iter().position(|op| *op == from).unwrap();
+            proto.code[index] = to;
+            let decoded = bytecode::decode(&bytecode::encode(&tampered)).unwrap();
+            let result = vm::run_module(&decoded, &mut io::sink(), interpreter::Options::default());
+            assert!(matches!(result, Err(Error::Bytecode { message }) if message == "the bytecode is corrupt"));
+        }
+    }
+
+    #[test]
+    fn test_repl() {
+        let input = r"(define x 10)
+            (+ x 1)
+            (print-num
+              (* x 2))
+            (foo 1)
+            (not #f)
+            (fun (a b) a)
+            (define sq (fun (n) (* n n)))
+            sq";
+        for backend in [Backend::Tree, Backend::Vm] {
+            let mut writer = Vec::new();
+            let mut err_writer = Vec::new();
+            let options = interpreter::Options {
+                backend,
+                ..Default::default()
+            };
+            repl::run(input.as_bytes(), &mut writer, &mut err_writer, options).unwrap();
+            let output = String::from_utf8(writer).unwrap();
+            let output = output.replace("mini-lisp> ", "").replace("       ... ", "");
+            let values: Vec<&str> = output.lines().filter(|line| !line.is_empty()).collect();
+            assert_eq!(values, vec!["11", "20", "#t", "#<procedure (a b)>", "#<procedure sq (n)>"]);
+            let errors = String::from_utf8(err_writer).unwrap();
+            assert!(errors.contains("variable 'foo' not found"));
+        }
+
+        assert_eq!(repl::paren_depth("(define f (fun (x)"), 2);
+        assert_eq!(repl::paren_depth("(+ 1 2))"), -1);
+        assert_eq!(repl::paren_depth("(+ 1 ; (\n 2)"), 0);
+        assert_eq!(repl::paren_depth("(+ 1 #| ) #| |# |# 2"), 1);
+        assert_eq!(repl::paren_depth("(+ 1 2) #| #| ( |#"), 1);
+        assert_eq!(repl::paren_depth("(+ 1 #;(f) 2"), 1);
+
+        // A line that is not UTF-8 is reported and skipped, and a form left open at the
+        // end of input is reported.
+        let mut writer = Vec::new();
+        let mut err_writer = Vec::new();
+        let input = b"(+ 1 2)\n(print-num \xff)\n(+ 2 3)\n(define f (fun (x)\n  (+ x";
+        repl::run(&input[..], &mut writer, &mut err_writer, Default::default()).unwrap();
+        let output = String::from_utf8(writer).unwrap();
+        assert_eq!(output.matches(|c: char| c.is_ascii_digit()).collect::<String>(), "35");
+        let errors = String::from_utf8(err_writer).unwrap();
+        assert!(errors.contains("stream did not contain valid UTF-8"), "{}", errors);
+        assert!(errors.contains("<repl>:2:7"), "{}", errors);
+        assert!(errors.contains("input ended before the form was closed"), "{}", errors);
+
+        // A closed output ends the session instead of panicking.
+        struct Closed;
+        impl Write for Closed {
+            fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+                Err(io::ErrorKind::BrokenPipe.into())
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+        let result = repl::run("(+ 1 2)\n".as_bytes(), &mut Closed, &mut Vec::new(), Default::default());
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::BrokenPipe);
     }
 
     #[test]
@@ -308,9 +1435,9 @@ mod tests {
               (fun (x) (fun (y) (+ x y))))
             (define z (add-x 10))
             (print-num (z 1))";
-        let program = parser::parse(&unparsed).unwrap();
+        let program = parser::parse(unparsed).unwrap();
         let mut writer = Vec::new();
-        interpreter::run(program, &mut writer).unwrap();
+        run(program, &mut writer).unwrap();
         let output = String::from_utf8(writer).unwrap();
         assert_eq!(output, "11\n");
 
@@ -318,10 +1445,228 @@ mod tests {
               (fun (f x) (f x)))
             (print-num
               (foo (fun (x) (- x 1)) 10))";
-        let program = parser::parse(&unparsed).unwrap();
+        let program = parser::parse(unparsed).unwrap();
         let mut writer = Vec::new();
-        interpreter::run(program, &mut writer).unwrap();
+        run(program, &mut writer).unwrap();
         let output = String::from_utf8(writer).unwrap();
         assert_eq!(output, "9\n");
     }
+
+    /// Programs within what `emit_c` and `emit_wat` support, including ones failing at run time.
+    const COMPILED_PROGRAMS: [&str; 13] = [
+        "(print-num ((fun (x) (+ x 1)) 3))
+        (define x 0)
+        (print-num ((fun (x y z) (+ x (* y z))) 10 20 30))
+        (print-num x)",
+        "(define fact (fun (n) (if (< n 3) n (* n (fact (- n 1))))))
+        (print-num (fact 20))
+        (define fib (fun (x) (if (< x 2) x (+ (fib (- x 1)) (fib (- x 2))))))
+        (print-num (fib 15))",
+        "(define add-x (fun (x) (fun (y) (+ x y))))
+        (define z (add-x 10))
+        (print-num (z 1))
+        (define foo (fun (f x) (f x)))
+        (print-num (foo (fun (x) (- x 1)) 10))",
+        "(define dist-square (fun (x y)
+          (define square (fun (x) (* x x)))
+          (+ (square x) (square y))))
+        (print-num (dist-square 3 4))",
+        "(define loop (fun (n acc) (if (= n 0) acc (loop (- n 1) (+ acc n)))))
+        (print-num (loop 100000 0))
+        (define is-even (fun (n) (or (= n 0) (is-odd (- n 1)))))
+        (define is-odd (fun (n) (and (not (= n 0)) (is-even (- n 1)))))
+        (print-bool (is-even 1001))",
+        "(print-bool (or #f (> 2 1) #f))
+        (print-num (mod -7 3))
+        (print-num (/ -12 4))
+        (print-bool (and #t (= 1 2)))
+        (print-num (+ 1 #t))",
+        "(define f (fun (n) (+ 1 (f n))))
+        (f 0)",
+        "(define f (fun (n) (and #t (g n))))
+        (define g (fun (n) n))
+        (print-num 1)
+        (f 2)",
+        "(print-num (* 4611686018427387904 2))",
+        "((fun (x) x))",
+        "(print-num undefined)",
+        "(define x 1)
+            (x 2)",
+        "(print-bool (= 1 1 2))
+        (print-bool (= 3 3 3 3))
+        (print-bool (= 1 2 (+ 1 #t)))",
+    ];
+
+    fn compiled_options() -> interpreter::Options {
+        interpreter::Options {
+            overflow: OverflowMode::Error,
+            max_depth: 50,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_optimize() {
+        let optimized = |unparsed: &str| -> Vec<ExpKind> {
+            let mut program = parser::parse(unparsed).unwrap();
+            optimize::optimize(&mut program, OverflowMode::default());
+            let stmts = program.stmts.into_iter();
+            stmts
+                .map(|stmt| match stmt.kind {
+                    StmtKind::ExpStmt { exp } | StmtKind::PrintStmt { exp, .. } | StmtKind::DefStmt { exp, .. } => exp.kind,
+                })
+                .collect()
+        };
+
+        let kinds = optimized("(print-num (+ 1 (* 4 5 6) (/ 8 3)))");
+        assert_eq!(kinds[0], ExpKind::Num("371/3".parse().unwrap()));
+        let kinds = optimized("(if (> 1 2) a (not (or #f #f)))");
+        assert_eq!(kinds[0], ExpKind::Bool(true));
+        let kinds = optimized("(if (= 1 1) a b)");
+        assert_eq!(kinds[0], ExpKind::Id("a".to_string()));
+        let kinds = optimized("(print-num (/ 1 (- 2 2)))");
+        assert!(matches!(&kinds[0], ExpKind::NumExp { args, .. } if args[1].kind == ExpKind::Num(0.into())));
+        let kinds = optimized("(print-num (* 1e308 10.0)) (print-num (- (* 1e308 10.0) (* 1e308 10.0)))");
+        assert!(kinds.iter().all(|kind| matches!(kind, ExpKind::NumExp { .. })));
+        let kinds = optimized("(and #t x #t #f y)");
+        assert!(matches!(&kinds[0], ExpKind::LogicalExp { args, .. } if args.len() == 4 && args[3].kind == ExpKind::Bool(false)));
+        let kinds = optimized("(or x #f)");
+        assert!(matches!(&kinds[0], ExpKind::LogicalExp { args, .. } if args.len() == 2));
+
+        let kinds = optimized("(define sq (fun (x) (* x x))) (print-num (sq 3)) (print-num (sq #t))");
+        assert_eq!(kinds[1], ExpKind::Num(9.into()));
+        assert!(matches!(kinds[2], ExpKind::FunCall { .. }));
+        let kinds = optimized("(define sq (fun (x) (* x x))) (print-num (sq 3)) (define sq (fun (x) x))");
+        assert!(matches!(kinds[1], ExpKind::FunCall { .. }));
+        let kinds = optimized("(define sq (fun (x) (* x x))) (define f (fun (sq) (sq 3)))");
+        assert!(matches!(&kinds[1], ExpKind::FunExp(lambda) if matches!(lambda.body.kind, ExpKind::FunCall { .. })));
+
+        let programs = COMPILED_PROGRAMS.iter().chain(&[
+            "(define sq (fun (x) (* x x)))
+            (print-num (sq (sq 3)))
+            (print-bool (if (< (sq 2) 5) (and #t (> 3 2)) #f))
+            (print-num (sq #t))",
+            "(print-num (+ 1 (/ 10 (- 2 2))))",
+            "(define x 5) (print-bool (and #t x #f))",
+        ]);
+        for unparsed in programs {
+            let mut outputs = Vec::new();
+            for optimize in [false, true] {
+                let options = interpreter::Options {
+                    optimize,
+                    ..compiled_options()
+                };
+                let mut writer = Vec::new();
+                let result = run_with_options(parser::parse(unparsed).unwrap(), &mut writer, options);
+                outputs.push((String::from_utf8(writer).unwrap(), result.map_err(|err| (err.to_string(), err.span()))));
+            }
+            assert_eq!(outputs[0], outputs[1], "{}", unparsed);
+        }
+    }
+
+    #[test]
+    fn test_emit_c() {
+        let dir = std::env::temp_dir().join(format!("mini-lisp-emit-c-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let options = compiled_options();
+        for (index, unparsed) in COMPILED_PROGRAMS.iter().enumerate() {
+            let mut expected = Vec::new();
+            let result = interpreter::run_with_options(parser::parse(unparsed).unwrap(), &mut expected, options.clone());
+            let source = emit_c::emit(parser::parse(unparsed).unwrap(), "test.lsp", &options).unwrap();
+            let c_file = dir.join(format!("{}.c", index));
+            let exe = dir.join(index.to_string());
+            std::fs::write(&c_file, source).unwrap();
+            let status = std::process::Command::new("cc")
+                .arg("-o")
+                .arg(&exe)
+                .arg(&c_file)
+                .status()
+                .expect("cc is needed to test the C backend");
+            assert!(status.success());
+
+            let output = std::process::Command::new(&exe).output().unwrap();
+            assert_eq!(String::from_utf8_lossy(&output.stdout), String::from_utf8_lossy(&expected));
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            match result {
+                Ok(()) => assert!(output.status.success() && stderr.is_empty()),
+                Err(err) => {
+                    assert!(!output.status.success());
+                    assert_eq!(stderr, format!("{}\n --> test.lsp:{}\n", err, err.span().unwrap()));
+                }
+            }
+        }
+
+        // Frames of finished calls are freed, so a long tail-recursive loop runs in little memory.
+        let unparsed = "(define loop (fun (n) (if (= n 0) 0 (loop (- n 1))))) (print-num (loop 10000000))";
+        let source = emit_c::emit(parser::parse(unparsed).unwrap(), "test.lsp", &options).unwrap();
+        let c_file = dir.join("loop.c");
+        let exe = dir.join("loop");
+        std::fs::write(&c_file, source).unwrap();
+        assert!(std::process::Command::new("cc").arg("-o").arg(&exe).arg(&c_file).status().unwrap().success());
+        let output = std::process::Command::new("sh")
+            .arg("-c")
+            .arg(format!("ulimit -v 65536 && exec '{}'", exe.display()))
+            .output()
+            .unwrap();
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "0\n");
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let program = parser::parse("(print-num (+ 1 1/2))").unwrap();
+        let result = emit_c::emit(program, "test.lsp", &options);
+        assert!(matches!(result, Err(Error::Unsupported { what: "rational numbers", span, .. }) if span.col == 17));
+    }
+
+    #[test]
+    fn test_emit_wat() {
+        #[derive(Default)]
+        struct Host {
+            output: Vec<u8>,
+            error: Option<Error>,
+        }
+
+        let options = compiled_options();
+        let engine = wasmi::Engine::default();
+        let mut linker = wasmi::Linker::<Host>::new(&engine);
+        linker
+            .func_wrap("env", "print_num", |mut caller: wasmi::Caller<Host>, val: i64| {
+                writeln!(caller.data_mut().output, "{}", val).unwrap();
+            })
+            .unwrap()
+            .func_wrap("env", "print_bool", |mut caller: wasmi::Caller<Host>, val: i32| {
+                writeln!(caller.data_mut().output, "{}", if val != 0 { "#t" } else { "#f" }).unwrap();
+            })
+            .unwrap()
+            .func_wrap(
+                "env",
+                "fail",
+                |mut caller: wasmi::Caller<Host>, code: i32, span: i32, a: i32, b: i32, c: i32| {
+                    let memory = caller.get_export("memory").and_then(wasmi::Extern::into_memory).unwrap();
+                    let err = emit_wat::runtime_error(memory.data(&caller), code, span, a, b, c);
+                    caller.data_mut().error = Some(err);
+                    Err::<(), _>(wasmi::Error::new("runtime error"))
+                },
+            )
+            .unwrap();
+
+        for unparsed in COMPILED_PROGRAMS {
+            let mut expected = Vec::new();
+            let result = interpreter::run_with_options(parser::parse(unparsed).unwrap(), &mut expected, options.clone());
+            let source = emit_wat::emit(parser::parse(unparsed).unwrap(), &options).unwrap();
+            let module = wasmi::Module::new(&engine, &wat::parse_str(&source).unwrap()[..]).unwrap();
+            let mut store = wasmi::Store::new(&engine, Host::default());
+            let instance = linker.instantiate(&mut store, &module).unwrap().start(&mut store).unwrap();
+            let main = instance.get_typed_func::<(), ()>(&store, "main").unwrap();
+            let trapped = main.call(&mut store, ()).is_err();
+
+            let host = store.into_data();
+            assert_eq!(String::from_utf8_lossy(&host.output), String::from_utf8_lossy(&expected));
+            assert_eq!(trapped, result.is_err());
+            let describe = |err: &Error| (err.to_string(), err.span());
+            assert_eq!(host.error.as_ref().map(describe), result.as_ref().err().map(describe));
+        }
+
+        let program = parser::parse("(print-num (exact->inexact 1))").unwrap();
+        let result = emit_wat::emit(program, &options);
+        assert!(matches!(result, Err(Error::Unsupported { what: "inexact numbers", target: "WebAssembly", .. })));
+    }
 }
@@ -0,0 +1,223 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
+
+use num_bigint::BigInt;
+use num_rational::BigRational;
+use num_traits::{ToPrimitive, Zero};
+
+/// What happens when a fixnum operation does not fit in an `i64`.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum OverflowMode {
+    Wrap,
+    Error,
+    #[default]
+    Promote,
+}
+
+impl OverflowMode {
+    pub fn parse(mode: &str) -> Option<Self> {
+        match mode {
+            "wrap" => Some(OverflowMode::Wrap),
+            "error" => Some(OverflowMode::Error),
+            "promote" => Some(OverflowMode::Promote),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArithError {
+    Overflow,
+    DivisionByZero,
+    NoExactValue,
+}
+
+/// The numeric tower: exact integers (fixnums and bignums), exact rationals
+/// and inexact IEEE doubles. Exact values are always kept in their simplest
+/// representation, so `Big` never fits in an `i64` and `Ratio` is never whole.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Number {
+    Int(i64),
+    Big(BigInt),
+    Ratio(BigRational),
+    Float(f64),
+}
+
+impl Number {
+    fn to_big(&self) -> BigInt {
+        match self {
+            Number::Int(val) => BigInt::from(*val),
+            Number::Big(val) => val.clone(),
+            Number::Ratio(val) => val.to_integer(),
+            Number::Float(_) => unreachable!(),
+        }
+    }
+
+    fn to_ratio(&self) -> BigRational {
+        match self {
+            Number::Ratio(val) => val.clone(),
+            _ => BigRational::from_integer(self.to_big()),
+        }
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        match self {
+            Number::Int(val) => *val as f64,
+            Number::Big(val) => val.to_f64().unwrap_or(f64::NAN),
+            Number::Ratio(val) => val.to_f64().unwrap_or(f64::NAN),
+            Number::Float(val) => *val,
+        }
+    }
+
+    pub fn is_exact(&self) -> bool {
+        !matches!(self, Number::Float(_))
+    }
+
+    fn normalize(val: BigInt) -> Number {
+        match val.to_i64() {
+            Some(val) => Number::Int(val),
+            None => Number::Big(val),
+        }
+    }
+
+    fn normalize_ratio(val: BigRational) -> Number {
+        if val.is_integer() {
+            Number::normalize(val.to_integer())
+        } else {
+            Number::Ratio(val)
+        }
+    }
+
+    fn is_zero(&self) -> bool {
+        match self {
+            Number::Int(val) => *val == 0,
+            Number::Big(val) => val.is_zero(),
+            Number::Ratio(val) => val.is_zero(),
+            Number::Float(val) => *val == 0.0,
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn apply(
+        &self,
+        other: &Number,
+        mode: OverflowMode,
+        checked: fn(i64, i64) -> Option<i64>,
+        wrapping: fn(i64, i64) -> i64,
+        big: fn(BigInt, BigInt) -> BigInt,
+        ratio: fn(BigRational, BigRational) -> BigRational,
+        float: fn(f64, f64) -> f64,
+    ) -> Result<Number, ArithError> {
+        match (self, other) {
+            (Number::Float(_), _) | (_, Number::Float(_)) => Ok(Number::Float(float(self.to_f64(), other.to_f64()))),
+            (Number::Ratio(_), _) | (_, Number::Ratio(_)) => Ok(Number::normalize_ratio(ratio(self.to_ratio(), other.to_ratio()))),
+            (Number::Int(a), Number::Int(b)) => match (checked(*a, *b), mode) {
+                (Some(val), _) => Ok(Number::Int(val)),
+                (None, OverflowMode::Wrap) => Ok(Number::Int(wrapping(*a, *b))),
+                (None, OverflowMode::Error) => Err(ArithError::Overflow),
+                (None, OverflowMode::Promote) => Ok(Number::normalize(big(self.to_big(), other.to_big()))),
+            },
+            _ => Ok(Number::normalize(big(self.to_big(), other.to_big()))),
+        }
+    }
+
+    pub fn add(&self, other: &Number, mode: OverflowMode) -> Result<Number, ArithError> {
+        self.apply(other, mode, i64::checked_add, i64::wrapping_add, |a, b| a + b, |a, b| a + b, |a, b| a + b)
+    }
+
+    pub fn sub(&self, other: &Number, mode: OverflowMode) -> Result<Number, ArithError> {
+        self.apply(other, mode, i64::checked_sub, i64::wrapping_sub, |a, b| a - b, |a, b| a - b, |a, b| a - b)
+    }
+
+    pub fn mul(&self, other: &Number, mode: OverflowMode) -> Result<Number, ArithError> {
+        self.apply(other, mode, i64::checked_mul, i64::wrapping_mul, |a, b| a * b, |a, b| a * b, |a, b| a * b)
+    }
+
+    /// Exact division yields a rational unless the quotient is whole.
+    pub fn div(&self, other: &Number, mode: OverflowMode) -> Result<Number, ArithError> {
+        if other.is_zero() {
+            return Err(ArithError::DivisionByZero);
+        }
+        match (self, other) {
+            (Number::Int(a), Number::Int(b)) if a.checked_rem(*b).is_none_or(|rem| rem == 0) => {
+                self.apply(other, mode, i64::checked_div, i64::wrapping_div, |a, b| a / b, |a, b| a / b, |a, b| a / b)
+            }
+            (Number::Float(_), _) | (_, Number::Float(_)) => Ok(Number::Float(self.to_f64() / other.to_f64())),
+            _ => Ok(Number::normalize_ratio(self.to_ratio() / other.to_ratio())),
+        }
+    }
+
+    /// Remainder truncated towards zero, taking the sign of the dividend.
+    pub fn rem(&self, other: &Number, mode: OverflowMode) -> Result<Number, ArithError> {
+        if other.is_zero() {
+            return Err(ArithError::DivisionByZero);
+        }
+        self.apply(other, mode, i64::checked_rem, i64::wrapping_rem, |a, b| a % b, |a, b| a % b, |a, b| a % b)
+    }
+
+    pub fn to_inexact(&self) -> Number {
+        Number::Float(self.to_f64())
+    }
+
+    pub fn to_exact(&self) -> Result<Number, ArithError> {
+        match self {
+            Number::Float(val) => BigRational::from_float(*val)
+                .map(Number::normalize_ratio)
+                .ok_or(ArithError::NoExactValue),
+            _ => Ok(self.clone()),
+        }
+    }
+}
+
+impl PartialOrd for Number {
+    fn partial_cmp(&self, other: &Number) -> Option<Ordering> {
+        match (self, other) {
+            (Number::Int(a), Number::Int(b)) => a.partial_cmp(b),
+            (Number::Float(_), _) | (_, Number::Float(_)) => self.to_f64().partial_cmp(&other.to_f64()),
+            (Number::Ratio(_), _) | (_, Number::Ratio(_)) => self.to_ratio().partial_cmp(&other.to_ratio()),
+            _ => self.to_big().partial_cmp(&other.to_big()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseNumberError;
+
+impl FromStr for Number {
+    type Err = ParseNumberError;
+
+    fn from_str(literal: &str) -> Result<Self, Self::Err> {
+        if let Some((numer, denom)) = literal.split_once('/') {
+            let numer: BigInt = numer.parse().map_err(|_| ParseNumberError)?;
+            let denom: BigInt = denom.parse().map_err(|_| ParseNumberError)?;
+            if denom.is_zero() {
+                return Err(ParseNumberError);
+            }
+            Ok(Number::normalize_ratio(BigRational::new(numer, denom)))
+        } else if literal.contains(['.', 'e', 'E']) {
+            literal.parse().map(Number::Float).map_err(|_| ParseNumberError)
+        } else {
+            literal.parse().map(Number::normalize).map_err(|_| ParseNumberError)
+        }
+    }
+}
+
+impl From<i64> for Number {
+    fn from(val: i64) -> Self {
+        Number::Int(val)
+    }
+}
+
+impl fmt::Display for Number {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Number::Int(val) => write!(f, "{}", val),
+            Number::Big(val) => write!(f, "{}", val),
+            Number::Ratio(val) => write!(f, "{}/{}", val.numer(), val.denom()),
+            Number::Float(val) if val.is_nan() => write!(f, "+nan.0"),
+            Number::Float(val) if val.is_infinite() => write!(f, "{}inf.0", if *val > 0.0 { "+" } else { "-" }),
+            Number::Float(val) => write!(f, "{:?}", val),
+        }
+    }
+}
@@ -0,0 +1,204 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::ast::*;
+use crate::env::Value;
+use crate::interpreter;
+use crate::number::{Number, OverflowMode};
+
+/// Simplifies `program` before it is resolved: operations on constants are folded,
+/// `if`s with a constant condition are replaced by the branch taken, and calls of small
+/// non-recursive functions with constant arguments are replaced by their result.
+///
+/// Anything that would fail at run time is left in place, so the program still fails
+/// the same way. A pruned `if` reports a type error at the branch it was replaced by,
+/// and a call replaced by its result no longer counts towards the recursion limit.
+pub fn optimize(program: &mut Program, mode: OverflowMode) {
+    let mut definitions: HashMap<String, usize> = HashMap::new();
+    for stmt in &program.stmts {
+        if let StmtKind::DefStmt { id, .. } = &stmt.kind {
+            *definitions.entry(id.to_string()).or_default() += 1;
+        }
+    }
+
+    let mut optimizer = Optimizer::new(mode);
+    for stmt in &mut program.stmts {
+        optimizer.stmt(stmt);
+        // A global defined only once holds that function from here on, so later
+        // statements may call it in place.
+        if let StmtKind::DefStmt { id, exp } = &stmt.kind {
+            if let ExpKind::FunExp(lambda) = &exp.kind {
+                let name = id.to_string();
+                if definitions[&name] == 1 && is_simple(lambda) {
+                    optimizer.functions.insert(name, lambda.clone());
+                }
+            }
+        }
+    }
+}
+
+/// Simplifies a statement on its own, for when the statements that follow it are not known yet.
+pub fn optimize_stmt(stmt: &mut Stmt, mode: OverflowMode) {
+    Optimizer::new(mode).stmt(stmt);
+}
+
+/// Whether calling `lambda` can only compute a value: it defines nothing and calls nothing.
+fn is_simple(lambda: &Lambda) -> bool {
+    fn calls_nothing(exp: &Exp) -> bool {
+        match &exp.kind {
+            ExpKind::Bool(_) | ExpKind::Num(_) | ExpKind::Id(_) | ExpKind::Var { .. } => true,
+            ExpKind::NumExp { args, .. } | ExpKind::LogicalExp { args, .. } => args.iter().all(|arg| calls_nothing(arg)),
+            ExpKind::IfExp { cond_exp, then_exp, else_exp } => {
+                calls_nothing(cond_exp) && calls_nothing(then_exp) && calls_nothing(else_exp)
+            }
+            ExpKind::FunExp(_) | ExpKind::FunCall { .. } => false,
+        }
+    }
+    lambda.def_stmts.is_empty() && calls_nothing(&lambda.body)
+}
+
+fn constant_bool(exp: &Exp) -> Option<bool> {
+    match exp.kind {
+        ExpKind::Bool(val) => Some(val),
+        _ => None,
+    }
+}
+
+fn is_constant(exp: &Exp) -> bool {
+    matches!(exp.kind, ExpKind::Bool(_) | ExpKind::Num(_))
+}
+
+/// Replaces every use of a parameter in `exp` by its argument, keeping the span of the use.
+fn substitute(exp: &mut Exp, params: &[Exp], args: &[Box<Exp>]) {
+    match &mut exp.kind {
+        ExpKind::Id(name) => {
+            if let Some(index) = params.iter().position(|param| param.to_string() == *name) {
+                exp.kind = args[index].kind.clone();
+            }
+        }
+        ExpKind::NumExp { args: operands, .. } | ExpKind::LogicalExp { args: operands, .. } => {
+            operands.iter_mut().for_each(|operand| substitute(operand, params, args));
+        }
+        ExpKind::IfExp { cond_exp, then_exp, else_exp } => {
+            substitute(cond_exp, params, args);
+            substitute(then_exp, params, args);
+            substitute(else_exp, params, args);
+        }
+        _ => {}
+    }
+}
+
+struct Optimizer {
+    mode: OverflowMode,
+    /// Globals known to hold a simple function wherever they are not shadowed.
+    functions: HashMap<String, Rc<Lambda>>,
+    /// Names bound by each enclosing function, innermost last.
+    scopes: Vec<Vec<String>>,
+}
+
+impl Optimizer {
+    fn new(mode: OverflowMode) -> Self {
+        Optimizer {
+            mode,
+            functions: HashMap::new(),
+            scopes: Vec::new(),
+        }
+    }
+
+    fn stmt(&mut self, stmt: &mut Stmt) {
+        match &mut stmt.kind {
+            StmtKind::ExpStmt { exp } | StmtKind::PrintStmt { exp, .. } | StmtKind::DefStmt { exp, .. } => self.exp(exp),
+        }
+    }
+
+    /// The simple function `func` is known to evaluate to, if any.
+    fn callee(&self, func: &Exp) -> Option<Rc<Lambda>> {
+        match &func.kind {
+            ExpKind::FunExp(lambda) if is_simple(lambda) => Some(lambda.clone()),
+            ExpKind::Id(name) if !self.scopes.iter().any(|scope| scope.contains(name)) => self.functions.get(name).cloned(),
+            _ => None,
+        }
+    }
+
+    fn exp(&mut self, exp: &mut Exp) {
+        match &mut exp.kind {
+            ExpKind::Bool(_) | ExpKind::Num(_) | ExpKind::Id(_) | ExpKind::Var { .. } => {}
+            ExpKind::NumExp { op, args } => {
+                args.iter_mut().for_each(|arg| self.exp(arg));
+                let nums: Option<Vec<_>> = args
+                    .iter()
+                    .map(|arg| match &arg.kind {
+                        ExpKind::Num(val) => Some(val.clone()),
+                        _ => None,
+                    })
+                    .collect();
+                // Left alone when it fails, so that it fails at run time, and when it is an
+                // infinity or NaN, which has no literal.
+                match nums.map(|nums| interpreter::apply_num_op(*op, &nums, self.mode, exp.span)) {
+                    Some(Ok(Value::Num(Number::Float(val)))) if !val.is_finite() => {}
+                    Some(Ok(Value::Num(val))) => exp.kind = ExpKind::Num(val),
+                    Some(Ok(Value::Bool(val))) => exp.kind = ExpKind::Bool(val),
+                    _ => {}
+                }
+            }
+            ExpKind::LogicalExp { op: LogicalOp::Not, args } => {
+                self.exp(&mut args[0]);
+                if let Some(val) = constant_bool(&args[0]) {
+                    exp.kind = ExpKind::Bool(!val);
+                }
+            }
+            ExpKind::LogicalExp { op, args } => {
+                args.iter_mut().for_each(|arg| self.exp(arg));
+                // `and` stops at the first false arm and `or` at the first true one.
+                let stop = *op == LogicalOp::Or;
+                if let Some(index) = args.iter().position(|arg| constant_bool(arg) == Some(stop)) {
+                    args.truncate(index + 1);
+                }
+                // The arms that let evaluation go on stay, so that errors still name the
+                // argument by its position in the source.
+                if let Some(vals) = args.iter().map(|arg| constant_bool(arg)).collect::<Option<Vec<_>>>() {
+                    exp.kind = ExpKind::Bool(*vals.last().unwrap());
+                }
+            }
+            ExpKind::IfExp { cond_exp, then_exp, else_exp } => {
+                self.exp(cond_exp);
+                self.exp(then_exp);
+                self.exp(else_exp);
+                if let Some(cond) = constant_bool(cond_exp) {
+                    let ExpKind::IfExp { then_exp, else_exp, .. } = std::mem::replace(&mut exp.kind, ExpKind::Bool(cond)) else {
+                        unreachable!()
+                    };
+                    *exp = *if cond { then_exp } else { else_exp };
+                }
+            }
+            ExpKind::FunExp(lambda) => {
+                let lambda = Rc::make_mut(lambda);
+                let mut scope: Vec<String> = lambda.params.iter().map(|param| param.to_string()).collect();
+                for stmt in &lambda.def_stmts {
+                    if let StmtKind::DefStmt { id, .. } = &stmt.kind {
+                        scope.push(id.to_string());
+                    }
+                }
+                self.scopes.push(scope);
+                lambda.def_stmts.iter_mut().for_each(|stmt| self.stmt(stmt));
+                self.exp(&mut lambda.body);
+                self.scopes.pop();
+            }
+            ExpKind::FunCall { func, args } => {
+                self.exp(func);
+                args.iter_mut().for_each(|arg| self.exp(arg));
+                let Some(lambda) = self.callee(func) else { return };
+                if lambda.params.len() != args.len() || !args.iter().all(|arg| is_constant(arg)) {
+                    return;
+                }
+                let mut body = lambda.body.clone();
+                substitute(&mut body, &lambda.params, args);
+                self.exp(&mut body);
+                // Only a call that is certain to succeed can be replaced.
+                if is_constant(&body) {
+                    exp.kind = body.kind;
+                }
+            }
+        }
+    }
+}
@@ -1,32 +1,351 @@
+use std::rc::Rc;
+
+use pest::error::{ErrorVariant, InputLocation, LineColLocation};
 use pest::{Parser as ParserTrait, iterators::Pair};
 use pest_derive::Parser;
 
 use crate::ast::*;
+use crate::error::Error;
+use crate::number::Number;
 
 #[derive(Parser)]
 #[grammar = "grammar.pest"]
 struct Parser;
 
-pub fn parse(input: &str) -> Result<Program, String> {
-    let mut pairs = Parser::parse(Rule::PROGRAM, input).map_err(|e| format!("{}", e))?;
+pub fn parse(input: &str) -> Result<Program, Error> {
+    let mut pairs = Parser::parse(Rule::PROGRAM, input).map_err(syntax_error)?;
     let program = parse_program(pairs.next().unwrap())?;
     Ok(program)
 }
 
-fn parse_program(pair: Pair<Rule>) -> Result<Program, String> {
+/// Whether `input` holds nothing but whitespace and comments.
+pub fn is_blank(input: &str) -> bool {
+    Parser::parse(Rule::ONLY_COMMENTS, input).is_ok()
+}
+
+/// Parses every top-level form independently, returning the forms that parsed
+/// together with all syntax errors in source order.
+pub fn parse_recovering(input: &str) -> (Program, Vec<Error>) {
+    let err = match parse(input) {
+        Ok(program) => return (program, Vec::new()),
+        Err(err) => err,
+    };
+
+    let mut stmts = Vec::new();
+    let mut comments = Vec::new();
+    let mut errors = Vec::new();
+    let forms = split_forms(input);
+    for form in &forms {
+        let chunk = &input[form.start..form.end];
+        if chunk.starts_with(')') {
+            errors.push(Error::Syntax { message: "unexpected ')'".to_string(), span: *form });
+            continue;
+        }
+        if comment_end(chunk, 0).is_some_and(|(_, open)| open > 0) {
+            let span = Span::new(form.start, form.start + 2, form.line, form.col);
+            errors.push(Error::Syntax { message: "unclosed block comment".to_string(), span });
+            continue;
+        }
+        let rule = if comment_end(chunk, 0).is_some() || chunk.starts_with("#;") { Rule::ONLY_COMMENTS } else { Rule::SINGLE_STMT };
+        let result = Parser::parse(rule, chunk)
+            .map_err(syntax_error)
+            .and_then(|mut pairs| {
+                let pair = pairs.next().unwrap();
+                let mut chunk_comments = Vec::new();
+                parse_comments(pair.clone(), &mut chunk_comments);
+                let stmt = pair.into_inner().find(|pair| pair.as_rule() == Rule::STMT).map(parse_stmt).transpose()?;
+                Ok((stmt, chunk_comments))
+            });
+        match result {
+            Ok((stmt, chunk_comments)) => {
+                if let Some(mut stmt) = stmt {
+                    shift_stmt(&mut stmt, form);
+                    stmts.push(stmt);
+                }
+                comments.extend(chunk_comments.into_iter().map(|comment| Comment { span: shift_span(comment.span, form), ..comment }));
+            }
+            Err(Error::Syntax { message, span }) => {
+                errors.push(Error::Syntax { message, span: shift_span(span, form) });
+            }
+            Err(err) => errors.push(err),
+        }
+    }
+    if forms.is_empty() {
+        errors.push(err);
+    }
+    (Program { stmts, comments }, errors)
+}
+
+/// Splits the input into top-level forms. An open parenthesis at the start of a
+/// line always begins a new form, so a missing `)` only affects its own form.
+/// Comments outside of forms are forms of their own, and a `#;` comment spans
+/// the form it comments out.
+fn split_forms(input: &str) -> Vec<Span> {
+    let mut forms = Vec::new();
+    let mut current: Option<Span> = None;
+    let mut depth = 0;
+    let (mut line, mut col) = (1, 1);
+    let mut comment_until = 0;
+    let mut datum_comments = Vec::new();
+
+    for (pos, char) in input.char_indices() {
+        // Comments separate forms like whitespace and the parentheses in them do not count.
+        let mut c = char;
+        let mut comment = None;
+        if pos < comment_until {
+            c = ' ';
+        } else if let Some((end, _)) = comment_end(input, pos) {
+            comment = Some(Span::new(pos, end, line, col));
+            comment_until = end;
+            c = ' ';
+        } else if input[pos..].starts_with("#;") {
+            if current.is_none() {
+                datum_comments.push(Span::new(pos, pos + 2, line, col));
+            }
+            comment_until = pos + 2;
+            c = ' ';
+        }
+        if c == '(' && col == 1 && depth > 0 {
+            if let Some(mut form) = current.take() {
+                form.end = form.start + input[form.start..pos].trim_end().len();
+                forms.push(form);
+            }
+            depth = 0;
+        }
+        match current.as_mut() {
+            None if c == ')' => forms.push(Span::new(pos, pos + 1, line, col)),
+            None if !c.is_whitespace() => {
+                current = Some(Span::new(pos, pos, line, col));
+                depth = if c == '(' { 1 } else { 0 };
+            }
+            None => {}
+            Some(form) if depth == 0 && (c.is_whitespace() || c == '(' || c == ')') => {
+                form.end = pos;
+                forms.push(*form);
+                current = None;
+                match c {
+                    '(' => {
+                        current = Some(Span::new(pos, pos, line, col));
+                        depth = 1;
+                    }
+                    ')' => forms.push(Span::new(pos, pos + 1, line, col)),
+                    _ => {}
+                }
+            }
+            Some(_) if depth == 0 => {}
+            Some(form) => {
+                match c {
+                    '(' => depth += 1,
+                    ')' => depth -= 1,
+                    _ => {}
+                }
+                if depth == 0 {
+                    form.end = pos + 1;
+                    forms.push(*form);
+                    current = None;
+                }
+            }
+        }
+        if let Some(comment) = comment.filter(|_| current.is_none()) {
+            forms.push(comment);
+        }
+        if char == '\n' {
+            line += 1;
+            col = 1;
+        } else {
+            col += 1;
+        }
+    }
+    if let Some(mut form) = current {
+        form.end = input.len();
+        forms.push(form);
+    }
+    merge_datum_comments(input, forms, datum_comments)
+}
+
+/// Joins every `#;` with the form it comments out. Comments in between, including
+/// other `#;` comments with their forms, become part of it.
+fn merge_datum_comments(input: &str, forms: Vec<Span>, datum_comments: Vec<Span>) -> Vec<Span> {
+    let mut merged: Vec<Span> = Vec::new();
+    let mut open: Vec<Span> = Vec::new();
+    let mut items: Vec<(Span, bool)> = forms.into_iter().map(|form| (form, false)).chain(datum_comments.into_iter().map(|datum| (datum, true))).collect();
+    items.sort_by_key(|(span, _)| span.start);
+    let close = |merged: &mut Vec<Span>, start: Span, end: usize| {
+        while merged.last().is_some_and(|form| form.start > start.start) {
+            merged.pop();
+        }
+        merged.push(Span::new(start.start, end, start.line, start.col));
+    };
+    for (span, is_datum_comment) in items {
+        if is_datum_comment {
+            open.push(span);
+            continue;
+        }
+        match open.pop() {
+            Some(start) if comment_end(input, span.start).is_none() => close(&mut merged, start, span.end),
+            start => {
+                open.extend(start);
+                merged.push(span);
+            }
+        }
+    }
+    // A `#;` with nothing after it is left for the parser to report.
+    if let Some(start) = open.first() {
+        close(&mut merged, *start, input.trim_end().len());
+    }
+    merged
+}
+
+/// The end of the `;` or `#|` comment starting at byte `pos` of `input`, if one does,
+/// and how many block comments are still open there. That is only ever more than
+/// zero when the input ends inside a block comment. The `;` of a `#;` is not a
+/// comment of its own, which is up to the caller to skip.
+pub fn comment_end(input: &str, pos: usize) -> Option<(usize, usize)> {
+    let rest = &input[pos..];
+    if rest.starts_with(';') {
+        let len = rest.find(['\n', '\r']).unwrap_or(rest.len());
+        return Some((pos + len, 0));
+    }
+    if !rest.starts_with("#|") {
+        return None;
+    }
+    let mut open = 0;
+    let mut at = 0;
+    while let Some(c) = rest[at..].chars().next() {
+        if rest[at..].starts_with("#|") {
+            open += 1;
+            at += 2;
+        } else if rest[at..].starts_with("|#") {
+            open -= 1;
+            at += 2;
+            if open == 0 {
+                return Some((pos + at, 0));
+            }
+        } else {
+            at += c.len_utf8();
+        }
+    }
+    Some((input.len(), open))
+}
+
+fn shift_span(span: Span, base: &Span) -> Span {
+    let (line, col) = if span.line == 1 {
+        (base.line, base.col + span.col - 1)
+    } else {
+        (base.line + span.line - 1, span.col)
+    };
+    Span::new(base.start + span.start, base.start + span.end, line, col)
+}
+
+fn shift_stmt(stmt: &mut Stmt, base: &Span) {
+    stmt.span = shift_span(stmt.span, base);
+    match &mut stmt.kind {
+        StmtKind::ExpStmt { exp } | StmtKind::PrintStmt { exp, .. } => shift_exp(exp, base),
+        StmtKind::DefStmt { id, exp } => {
+            shift_exp(id, base);
+            shift_exp(exp, base);
+        }
+    }
+}
+
+fn shift_exp(exp: &mut Exp, base: &Span) {
+    exp.span = shift_span(exp.span, base);
+    match &mut exp.kind {
+        ExpKind::Bool(_) | ExpKind::Num(_) | ExpKind::Id(_) | ExpKind::Var { .. } => {}
+        ExpKind::NumExp { args, .. } | ExpKind::LogicalExp { args, .. } => {
+            args.iter_mut().for_each(|arg| shift_exp(arg, base));
+        }
+        ExpKind::FunExp(lambda) => {
+            let lambda = Rc::make_mut(lambda);
+            lambda.params.iter_mut().for_each(|param| shift_exp(param, base));
+            lambda.def_stmts.iter_mut().for_each(|stmt| shift_stmt(stmt, base));
+            shift_exp(&mut lambda.body, base);
+        }
+        ExpKind::FunCall { func, args } => {
+            shift_exp(func, base);
+            args.iter_mut().for_each(|arg| shift_exp(arg, base));
+        }
+        ExpKind::IfExp { cond_exp, then_exp, else_exp } => {
+            shift_exp(cond_exp, base);
+            shift_exp(then_exp, base);
+            shift_exp(else_exp, base);
+        }
+    }
+}
+
+fn syntax_error(err: pest::error::Error<Rule>) -> Error {
+    let (start, end) = match err.location {
+        InputLocation::Pos(pos) => (pos, pos),
+        InputLocation::Span(span) => span,
+    };
+    let (line, col) = match err.line_col {
+        LineColLocation::Pos(pos) => pos,
+        LineColLocation::Span(pos, _) => pos,
+    };
+    // Comments can go anywhere and keywords such as `print-num` were never suggested
+    // before they had rules of their own, so none of them is worth listing.
+    let variant = match err.variant {
+        ErrorVariant::ParsingError { positives, negatives } => {
+            let listed = positives.iter().copied().filter(|rule| !matches!(rule, Rule::COMMENT | Rule::DATUM_COMMENT | Rule::print_type | Rule::to_inexact | Rule::to_exact)).collect::<Vec<_>>();
+            let positives = if listed.is_empty() && negatives.is_empty() { positives } else { listed };
+            ErrorVariant::ParsingError { positives, negatives }
+        }
+        variant => variant,
+    };
+    Error::Syntax {
+        message: variant.message().to_string(),
+        span: Span::new(start, end, line, col),
+    }
+}
+
+fn parse_program(pair: Pair<Rule>) -> Result<Program, Error> {
     assert!(pair.as_rule() == Rule::PROGRAM);
 
-    let stmts = pair.into_inner().map(|stmt| {
+    let mut comments = Vec::new();
+    parse_comments(pair.clone(), &mut comments);
+    let stmts = pair.into_inner().filter(|stmt| stmt.as_rule() == Rule::STMT).map(|stmt| {
         parse_stmt(stmt)
     }).collect::<Result<Vec<Stmt>, _>>()?;
     
-    Ok(Program{stmts})
+    Ok(Program{stmts, comments})
 }
 
-fn parse_stmt(pair: Pair<Rule>) -> Result<Stmt, String> {
+/// Collects the comments under `pair`, leaving out those inside another comment.
+fn parse_comments(pair: Pair<Rule>, comments: &mut Vec<Comment>) {
+    for pair in pair.into_inner() {
+        if pair.as_rule() != Rule::COMMENT {
+            parse_comments(pair, comments);
+            continue;
+        }
+        // Comments are skipped like whitespace, which pest does atomically, so the
+        // kind of comment is only known from how it starts.
+        let text = pair.as_str();
+        let kind = if text.starts_with(';') {
+            CommentKind::Line
+        } else if text.starts_with("#|") {
+            CommentKind::Block
+        } else {
+            CommentKind::Datum
+        };
+        comments.push(Comment { kind, text: text.to_string(), span: parse_span(&pair) });
+    }
+}
+
+/// The children of `pair` without the comments between them.
+fn inner(pair: Pair<Rule>) -> impl Iterator<Item = Pair<Rule>> {
+    pair.into_inner().filter(|pair| pair.as_rule() != Rule::COMMENT)
+}
+
+fn parse_span(pair: &Pair<Rule>) -> Span {
+    let span = pair.as_span();
+    let (line, col) = pair.line_col();
+    Span::new(span.start(), span.end(), line, col)
+}
+
+fn parse_stmt(pair: Pair<Rule>) -> Result<Stmt, Error> {
     assert!(pair.as_rule() == Rule::STMT);
 
-    let stmt = pair.into_inner().next().ok_or("Empty statement".to_string())?;
+    let stmt = inner(pair).next().unwrap();
     match stmt.as_rule() {
         Rule::EXP => parse_exp_stmt(stmt),
         Rule::DEF_STMT => parse_def_stmt(stmt),
@@ -35,42 +354,46 @@ fn parse_stmt(pair: Pair<Rule>) -> Result<Stmt, String> {
     }
 }
 
-fn parse_exp_stmt(pair: Pair<Rule>) -> Result<Stmt, String> {
+fn parse_exp_stmt(pair: Pair<Rule>) -> Result<Stmt, Error> {
     assert!(pair.as_rule() == Rule::EXP);
 
+    let span = parse_span(&pair);
     let exp = parse_exp(pair)?;
 
-    Ok(Stmt::ExpStmt{exp})
+    Ok(Stmt { kind: StmtKind::ExpStmt{exp}, span })
 }
 
-fn parse_def_stmt(pair: Pair<Rule>) -> Result<Stmt, String>{
+fn parse_def_stmt(pair: Pair<Rule>) -> Result<Stmt, Error>{
     assert!(pair.as_rule() == Rule::DEF_STMT);
     
-    let mut inner = pair.into_inner();
-    let id = parse_id(inner.next().unwrap())?;
-    let exp = parse_exp(inner.next().unwrap())?;
-    Ok(Stmt::DefStmt{id, exp})
+    let span = parse_span(&pair);
+    let mut def_stmt = inner(pair);
+    let id = parse_id(def_stmt.next().unwrap())?;
+    let mut exp = parse_exp(def_stmt.next().unwrap())?;
+    if let ExpKind::FunExp(lambda) = &mut exp.kind {
+        Rc::get_mut(lambda).unwrap().name = Some(id.to_string());
+    }
+    Ok(Stmt { kind: StmtKind::DefStmt{id, exp}, span })
     
 }
 
-fn parse_print_stmt(pair: Pair<Rule>) -> Result<Stmt, String>{
+fn parse_print_stmt(pair: Pair<Rule>) -> Result<Stmt, Error>{
     assert!(pair.as_rule() == Rule::PRINT_STMT);
 
-    let print_type = if pair.as_str().contains("print-bool") {
-        PrintType::PrintBool
-    } else {
-        PrintType::PrintNum
+    let span = parse_span(&pair);
+    let mut print_stmt = inner(pair);
+    let print_type = match print_stmt.next().unwrap().as_str() {
+        "print-bool" => PrintType::PrintBool,
+        _ => PrintType::PrintNum,
     };
-    
-    let inner = pair.into_inner().next().unwrap();
-    let exp = parse_exp(inner)?;
-    Ok(Stmt::PrintStmt{exp, print_type})
+    let exp = parse_exp(print_stmt.next().unwrap())?;
+    Ok(Stmt { kind: StmtKind::PrintStmt{exp, print_type}, span })
 }
 
-fn parse_exp(pair: Pair<Rule>) -> Result<Exp, String> {
+fn parse_exp(pair: Pair<Rule>) -> Result<Exp, Error> {
     assert!(pair.as_rule() == Rule::EXP);
 
-    let exp = pair.into_inner().next().unwrap();
+    let exp = inner(pair).next().unwrap();
     match exp.as_rule() {
         Rule::bool => parse_bool(exp),
         Rule::number => parse_num(exp),
@@ -84,7 +407,7 @@ fn parse_exp(pair: Pair<Rule>) -> Result<Exp, String> {
     }
 }
 
-fn parse_bool(pair: Pair<Rule>) -> Result<Exp, String> {
+fn parse_bool(pair: Pair<Rule>) -> Result<Exp, Error> {
     assert!(pair.as_rule() == Rule::bool);
     
     let val = match pair.as_str() {
@@ -92,25 +415,27 @@ fn parse_bool(pair: Pair<Rule>) -> Result<Exp, String> {
         "#f" => false,
         _ => unreachable!()
     };
-    Ok(Exp::Bool(val))
+    Ok(Exp { kind: ExpKind::Bool(val), span: parse_span(&pair) })
 }
 
-fn parse_num(pair: Pair<Rule>) -> Result<Exp, String> {
+fn parse_num(pair: Pair<Rule>) -> Result<Exp, Error> {
     assert!(pair.as_rule() == Rule::number);
     
-    let val: i64 = pair.as_str().parse().unwrap();
-    Ok(Exp::Num(val))
+    let span = parse_span(&pair);
+    let val: Number = pair.as_str().parse().unwrap();
+    Ok(Exp { kind: ExpKind::Num(val), span })
 }
 
-fn parse_id(string: Pair<Rule>) -> Result<Exp, String> {
+fn parse_id(string: Pair<Rule>) -> Result<Exp, Error> {
     let val = string.as_str().to_string();
-    Ok(Exp::Id(val))
+    Ok(Exp { kind: ExpKind::Id(val), span: parse_span(&string) })
 }
 
-fn parse_num_exp(pair: Pair<Rule>) -> Result<Exp, String> {
+fn parse_num_exp(pair: Pair<Rule>) -> Result<Exp, Error> {
     assert!(pair.as_rule() == Rule::NUM_OP);
     
-    let num_exp = pair.into_inner().next().unwrap();
+    let span = parse_span(&pair);
+    let num_exp = inner(pair).next().unwrap();
     let op = match num_exp.as_rule() {
         Rule::PLUS => NumOp::Plus,
         Rule::MINUS => NumOp::Minus,
@@ -120,46 +445,50 @@ fn parse_num_exp(pair: Pair<Rule>) -> Result<Exp, String> {
         Rule::GREATER => NumOp::Greater,
         Rule::SMALLER => NumOp::Smaller,
         Rule::EQUAL => NumOp::Equal,
+        Rule::TO_INEXACT => NumOp::ToInexact,
+        Rule::TO_EXACT => NumOp::ToExact,
         _ => unreachable!()
     };
-    let args = num_exp.into_inner().map(|exp| {
-        Box::new(parse_exp(exp).unwrap())
-    }).collect();
-    Ok(Exp::NumExp{op, args})
+    let args = inner(num_exp).filter(|pair| !matches!(pair.as_rule(), Rule::to_inexact | Rule::to_exact)).map(|exp| {
+        parse_exp(exp).map(Box::new)
+    }).collect::<Result<_, _>>()?;
+    Ok(Exp { kind: ExpKind::NumExp{op, args}, span })
 }
 
-fn parse_logical_exp(pair: Pair<Rule>) -> Result<Exp, String> {
+fn parse_logical_exp(pair: Pair<Rule>) -> Result<Exp, Error> {
     assert!(pair.as_rule() == Rule::LOGICAL_OP);
     
-    let logical_exp = pair.into_inner().next().unwrap();
+    let span = parse_span(&pair);
+    let logical_exp = inner(pair).next().unwrap();
     let op = match logical_exp.as_rule() {
         Rule::AND_OP => LogicalOp::And,
         Rule::OR_OP  => LogicalOp::Or,
         Rule::NOT_OP => LogicalOp::Not,
         _ => unreachable!()
     };
-    let args = logical_exp.into_inner().map(|exp| {
-        Box::new(parse_exp(exp).unwrap())
-    }).collect();
-    Ok(Exp::LogicalExp{op, args})
+    let args = inner(logical_exp).map(|exp| {
+        parse_exp(exp).map(Box::new)
+    }).collect::<Result<_, _>>()?;
+    Ok(Exp { kind: ExpKind::LogicalExp{op, args}, span })
 }
 
-fn parse_fun_exp(pair: Pair<Rule>) -> Result<Exp, String> {
+fn parse_fun_exp(pair: Pair<Rule>) -> Result<Exp, Error> {
     assert!(pair.as_rule() == Rule::FUN_EXP);
     
-    let mut fun_exp = pair.into_inner();
+    let span = parse_span(&pair);
+    let mut fun_exp = inner(pair);
 
     let ids = fun_exp.next().unwrap();
-    let params = ids.into_inner().map(|id| {
-        parse_id(id).unwrap()
-    }).collect();
+    let params = inner(ids).map(|id| {
+        parse_id(id)
+    }).collect::<Result<_, _>>()?;
 
     let mut stmts = Vec::new();
     let mut exp = None;
 
     let func_body = fun_exp.next().unwrap();
 
-    for pair in func_body.into_inner() {
+    for pair in inner(func_body) {
         match pair.as_rule() {
             Rule::DEF_STMT => {
                 stmts.push(parse_def_stmt(pair)?);
@@ -174,13 +503,15 @@ fn parse_fun_exp(pair: Pair<Rule>) -> Result<Exp, String> {
 
     let body = exp.unwrap();
 
-    Ok(Exp::FunExp { params, def_stmts: stmts, body: Box::new(body) })
+    let lambda = Lambda { name: None, params, def_stmts: stmts, body, slots: Rc::from([]) };
+    Ok(Exp { kind: ExpKind::FunExp(Rc::new(lambda)), span })
 }
 
-fn parse_fun_call(pair: Pair<Rule>) -> Result<Exp, String> {
+fn parse_fun_call(pair: Pair<Rule>) -> Result<Exp, Error> {
     assert!(pair.as_rule() == Rule::FUN_CALL);
     
-    let mut fun_call = pair.into_inner();
+    let span = parse_span(&pair);
+    let mut fun_call = inner(pair);
 
     let first_exp = fun_call.next().unwrap();
     let func = match first_exp.as_rule() {
@@ -190,19 +521,20 @@ fn parse_fun_call(pair: Pair<Rule>) -> Result<Exp, String> {
     };
 
     let args = fun_call.map(|exp| {
-        Box::new(parse_exp(exp).unwrap())
-    }).collect();
+        parse_exp(exp).map(Box::new)
+    }).collect::<Result<_, _>>()?;
 
-    Ok(Exp::FunCall{func, args})
+    Ok(Exp { kind: ExpKind::FunCall{func, args}, span })
 }
 
-fn parse_if_exp(pair: Pair<Rule>) -> Result<Exp, String> {
+fn parse_if_exp(pair: Pair<Rule>) -> Result<Exp, Error> {
     assert!(pair.as_rule() == Rule::IF_EXP);
 
-    let mut if_exp = pair.into_inner();
+    let span = parse_span(&pair);
+    let mut if_exp = inner(pair);
     let cond_exp = Box::new(parse_exp(if_exp.next().unwrap())?);
     let then_exp = Box::new(parse_exp(if_exp.next().unwrap())?);
     let else_exp = Box::new(parse_exp(if_exp.next().unwrap())?);
 
-    Ok(Exp::IfExp{cond_exp, then_exp, else_exp})
+    Ok(Exp { kind: ExpKind::IfExp{cond_exp, then_exp, else_exp}, span })
 }
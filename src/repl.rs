@@ -0,0 +1,127 @@
+use std::cell::RefCell;
+use std::io::{self, BufRead, Write};
+use std::rc::Rc;
+
+use crate::ast::Span;
+use crate::diagnostic::Diagnostic;
+use crate::env::*;
+use crate::error::Error;
+use crate::interpreter::{self, Backend, Context, Options};
+use crate::optimize;
+use crate::parser;
+use crate::vm;
+
+const PROMPT: &str = "mini-lisp> ";
+const CONTINUE_PROMPT: &str = "       ... ";
+
+pub fn start(options: Options) {
+    let stdin = std::io::stdin();
+    let stdout = std::io::stdout();
+    let stderr = std::io::stderr();
+    // A closed stdout or stderr ends the session; there is nowhere left to report it.
+    let _ = run(stdin.lock(), &mut stdout.lock(), &mut stderr.lock(), options);
+}
+
+pub fn run<R: BufRead, W: Write, E: Write>(mut reader: R, writer: &mut W, err_writer: &mut E, options: Options) -> io::Result<()> {
+    let eval_stmt: fn(_, _, &mut Context<W>) -> _ = match options.backend {
+        Backend::Tree => interpreter::eval_stmt,
+        Backend::Vm => vm::eval_stmt,
+    };
+    let env = Rc::new(RefCell::new(Env::new()));
+    let mut ctx = Context::new(writer, options);
+    let mut buffer = String::new();
+
+    loop {
+        let prompt = if buffer.is_empty() { PROMPT } else { CONTINUE_PROMPT };
+        write!(ctx.writer, "{}", prompt)?;
+        ctx.writer.flush()?;
+
+        let mut line = String::new();
+        match reader.read_line(&mut line) {
+            Ok(0) => {
+                writeln!(ctx.writer)?;
+                if !parser::is_blank(&buffer) {
+                    report(err_writer, &buffer, &unterminated(&buffer))?;
+                }
+                return Ok(());
+            }
+            Ok(_) => {}
+            // The line is consumed all the same, so the session can go on without it
+            // and without the form it was part of.
+            Err(err) if err.kind() == io::ErrorKind::InvalidData => {
+                report(err_writer, "", &Error::Io(err))?;
+                buffer.clear();
+                continue;
+            }
+            Err(err) => return Err(err),
+        }
+        buffer.push_str(&line);
+
+        if paren_depth(&buffer) > 0 {
+            continue;
+        }
+        let input = std::mem::take(&mut buffer);
+        if parser::is_blank(&input) {
+            continue;
+        }
+
+        let program = match parser::parse(&input) {
+            Ok(program) => program,
+            Err(err) => {
+                report(err_writer, &input, &err)?;
+                continue;
+            }
+        };
+        for mut stmt in program.stmts {
+            if ctx.options.optimize {
+                optimize::optimize_stmt(&mut stmt, ctx.options.overflow);
+            }
+            match eval_stmt(stmt, env.clone(), &mut ctx) {
+                Ok(Some(val)) => writeln!(ctx.writer, "{}", val)?,
+                Ok(None) => {}
+                Err(err) => {
+                    report(err_writer, &input, &err)?;
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// The error for input that ends before its last form is closed, pointing at the end.
+fn unterminated(input: &str) -> Error {
+    let end = input.trim_end().len();
+    let line_start = input[..end].rfind('\n').map_or(0, |pos| pos + 1);
+    let line = input[..end].matches('\n').count() + 1;
+    let col = input[line_start..end].chars().count() + 1;
+    Error::Syntax {
+        message: "input ended before the form was closed".to_string(),
+        span: Span::new(end, end, line, col),
+    }
+}
+
+fn report<E: Write>(err_writer: &mut E, input: &str, err: &Error) -> io::Result<()> {
+    write!(err_writer, "{}", Diagnostic::from_error(err).render("<repl>", input))
+}
+
+/// How many parentheses and block comments `input` leaves open. Parentheses in
+/// comments do not count.
+pub fn paren_depth(input: &str) -> i64 {
+    let mut depth = 0;
+    let mut pos = 0;
+    while let Some(c) = input[pos..].chars().next() {
+        if let Some((end, open)) = parser::comment_end(input, pos) {
+            depth += open as i64;
+            pos = end;
+            continue;
+        }
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            '#' if input[pos..].starts_with("#;") => pos += 1,
+            _ => {}
+        }
+        pos += c.len_utf8();
+    }
+    depth
+}
@@ -0,0 +1,92 @@
+use std::rc::Rc;
+
+use crate::ast::*;
+use crate::env::Env;
+
+/// Rewrites every variable reference in `stmt` into the address of its binding:
+/// a slot in one of the enclosing call frames, or a slot in `globals`.
+pub fn resolve_stmt(stmt: &mut Stmt, globals: &mut Env) {
+    Resolver { globals, scopes: Vec::new() }.stmt(stmt);
+}
+
+pub fn resolve(program: &mut Program, globals: &mut Env) {
+    for stmt in &mut program.stmts {
+        resolve_stmt(stmt, globals);
+    }
+}
+
+/// The slots of a call frame: the parameters, then every name defined inside the
+/// function body. Redefining a parameter reuses its slot.
+fn frame_slots(params: &[Exp], def_stmts: &[Stmt]) -> Vec<String> {
+    let mut slots: Vec<String> = params.iter().map(|param| param.to_string()).collect();
+    for stmt in def_stmts {
+        if let StmtKind::DefStmt { id, .. } = &stmt.kind {
+            let name = id.to_string();
+            if !slots.contains(&name) {
+                slots.push(name);
+            }
+        }
+    }
+    slots
+}
+
+struct Resolver<'a> {
+    globals: &'a mut Env,
+    scopes: Vec<Vec<String>>,
+}
+
+impl Resolver<'_> {
+    fn lookup(&mut self, name: &str) -> Addr {
+        for (depth, scope) in self.scopes.iter().rev().enumerate() {
+            if let Some(slot) = scope.iter().position(|slot| slot == name) {
+                return Addr::Local { depth, slot };
+            }
+        }
+        Addr::Global(self.globals.slot(name))
+    }
+
+    fn var(&mut self, exp: &mut Exp) {
+        if let ExpKind::Id(name) = &mut exp.kind {
+            let name = std::mem::take(name);
+            let addr = self.lookup(&name);
+            exp.kind = ExpKind::Var { name, addr };
+        }
+    }
+
+    fn stmt(&mut self, stmt: &mut Stmt) {
+        match &mut stmt.kind {
+            StmtKind::ExpStmt { exp } | StmtKind::PrintStmt { exp, .. } => self.exp(exp),
+            StmtKind::DefStmt { id, exp } => {
+                self.var(id);
+                self.exp(exp);
+            }
+        }
+    }
+
+    fn exp(&mut self, exp: &mut Exp) {
+        match &mut exp.kind {
+            ExpKind::Bool(_) | ExpKind::Num(_) | ExpKind::Var { .. } => {}
+            ExpKind::Id(_) => self.var(exp),
+            ExpKind::NumExp { args, .. } | ExpKind::LogicalExp { args, .. } => {
+                args.iter_mut().for_each(|arg| self.exp(arg));
+            }
+            ExpKind::IfExp { cond_exp, then_exp, else_exp } => {
+                self.exp(cond_exp);
+                self.exp(then_exp);
+                self.exp(else_exp);
+            }
+            ExpKind::FunExp(lambda) => {
+                let lambda = Rc::make_mut(lambda);
+                lambda.slots = frame_slots(&lambda.params, &lambda.def_stmts).into();
+                self.scopes.push(lambda.slots.to_vec());
+                lambda.def_stmts.iter_mut().for_each(|stmt| self.stmt(stmt));
+                self.exp(&mut lambda.body);
+                self.scopes.pop();
+            }
+            ExpKind::FunCall { func, args } => {
+                self.exp(func);
+                args.iter_mut().for_each(|arg| self.exp(arg));
+            }
+        }
+    }
+}
@@ -0,0 +1,323 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::ast::*;
+use crate::diagnostic;
+use crate::error::Error;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Type {
+    Num,
+    Bool,
+    Fun(Vec<Type>, Box<Type>),
+    Var(usize),
+}
+
+/// A type whose `vars` may stand for a different type at every use.
+#[derive(Debug, Clone)]
+struct Scheme {
+    vars: Vec<usize>,
+    ty: Type,
+}
+
+impl Scheme {
+    fn mono(ty: Type) -> Self {
+        Scheme { vars: Vec::new(), ty }
+    }
+}
+
+/// Infers the type of every expression in `program` without running it and reports
+/// every place where a value cannot have the type it is used at.
+///
+/// A name defined once in its scope is polymorphic in the statements after its
+/// definition. Names defined more than once, parameters, and uses of a name before
+/// its definition are not, so each of them has a single type.
+pub fn typecheck(program: &Program) -> Vec<Error> {
+    let mut checker = Checker {
+        bindings: Vec::new(),
+        scopes: Vec::new(),
+        errors: Vec::new(),
+    };
+    checker.block(HashMap::new(), &program.stmts);
+    checker.errors
+}
+
+struct Checker {
+    /// What each type variable has been unified with so far.
+    bindings: Vec<Option<Type>>,
+    scopes: Vec<HashMap<String, Scheme>>,
+    errors: Vec<Error>,
+}
+
+impl Checker {
+    fn fresh(&mut self) -> Type {
+        self.bindings.push(None);
+        Type::Var(self.bindings.len() - 1)
+    }
+
+    /// `ty` with every bound variable replaced by what it is bound to.
+    fn apply(&self, ty: &Type) -> Type {
+        match ty {
+            Type::Var(var) => match &self.bindings[*var] {
+                Some(bound) => self.apply(bound),
+                None => ty.clone(),
+            },
+            Type::Fun(params, ret) => Type::Fun(params.iter().map(|param| self.apply(param)).collect(), Box::new(self.apply(ret))),
+            _ => ty.clone(),
+        }
+    }
+
+    fn free_vars(&self, ty: &Type, vars: &mut HashSet<usize>) {
+        match self.apply(ty) {
+            Type::Var(var) => {
+                vars.insert(var);
+            }
+            Type::Fun(params, ret) => {
+                params.iter().for_each(|param| self.free_vars(param, vars));
+                self.free_vars(&ret, vars);
+            }
+            _ => {}
+        }
+    }
+
+    /// Fails with the variable and type that could only be equal if the type
+    /// contained itself, or with nothing when the two shapes differ.
+    fn unify(&mut self, a: &Type, b: &Type) -> Result<(), Option<(usize, Type)>> {
+        match (self.apply(a), self.apply(b)) {
+            (Type::Num, Type::Num) | (Type::Bool, Type::Bool) => Ok(()),
+            (Type::Var(a), Type::Var(b)) if a == b => Ok(()),
+            (Type::Var(var), ty) | (ty, Type::Var(var)) => {
+                let mut vars = HashSet::new();
+                self.free_vars(&ty, &mut vars);
+                if vars.contains(&var) {
+                    return Err(Some((var, ty)));
+                }
+                self.bindings[var] = Some(ty);
+                Ok(())
+            }
+            (Type::Fun(a_params, a_ret), Type::Fun(b_params, b_ret)) if a_params.len() == b_params.len() => {
+                for (a, b) in a_params.iter().zip(&b_params) {
+                    self.unify(a, b)?;
+                }
+                self.unify(&a_ret, &b_ret)
+            }
+            _ => Err(None),
+        }
+    }
+
+    /// Reports `exp` unless its type `found` can be the `expected` one.
+    fn expect(&mut self, exp: &Exp, found: &Type, expected: &Type) {
+        match self.unify(expected, found) {
+            Ok(()) => {}
+            Err(Some((var, ty))) => {
+                let mut names = HashMap::new();
+                let var = self.show(&Type::Var(var), &mut names);
+                let ty = self.show(&ty, &mut names);
+                self.errors.push(Error::InfiniteType { var, ty, span: exp.span });
+            }
+            Err(None) => {
+                let mut names = HashMap::new();
+                let expected = self.show(expected, &mut names);
+                let found = self.show(found, &mut names);
+                self.errors.push(Error::TypeMismatch { expected, found, span: exp.span });
+            }
+        }
+    }
+
+    /// Type variables are named `a`, `b`, ..., `z`, `a1`, `b1`, ... in order of
+    /// appearance, sharing `names` between the types of one message.
+    fn show(&self, ty: &Type, names: &mut HashMap<usize, String>) -> String {
+        match self.apply(ty) {
+            Type::Num => "number".to_string(),
+            Type::Bool => "boolean".to_string(),
+            Type::Var(var) => {
+                let count = names.len();
+                names
+                    .entry(var)
+                    .or_insert_with(|| {
+                        let letter = (b'a' + (count % 26) as u8) as char;
+                        match count / 26 {
+                            0 => letter.to_string(),
+                            round => format!("{}{}", letter, round),
+                        }
+                    })
+                    .clone()
+            }
+            Type::Fun(params, ret) => {
+                let mut out = "(".to_string();
+                for param in &params {
+                    out.push_str(&self.show(param, names));
+                    out.push(' ');
+                }
+                out.push_str("-> ");
+                out.push_str(&self.show(&ret, names));
+                out.push(')');
+                out
+            }
+        }
+    }
+
+    fn instantiate(&mut self, scheme: &Scheme) -> Type {
+        let fresh: HashMap<usize, Type> = scheme.vars.iter().map(|var| (*var, self.fresh())).collect();
+        substitute(&self.apply(&scheme.ty), &fresh)
+    }
+
+    /// Generalizes `name`'s type over the variables nothing else in scope refers to.
+    fn generalize(&mut self, name: &str) {
+        let scheme = self.scopes.last_mut().unwrap().remove(name).unwrap();
+        let mut env_vars = HashSet::new();
+        for other in self.scopes.iter().flat_map(|scope| scope.values()) {
+            let mut vars = HashSet::new();
+            self.free_vars(&other.ty, &mut vars);
+            env_vars.extend(vars.into_iter().filter(|var| !other.vars.contains(var)));
+        }
+        let mut vars = HashSet::new();
+        self.free_vars(&scheme.ty, &mut vars);
+        let scheme = Scheme {
+            vars: vars.difference(&env_vars).copied().collect(),
+            ty: self.apply(&scheme.ty),
+        };
+        self.scopes.last_mut().unwrap().insert(name.to_string(), scheme);
+    }
+
+    fn lookup(&mut self, name: &str, span: Span) -> Type {
+        let scheme = self.scopes.iter().rev().find_map(|scope| scope.get(name)).cloned();
+        match scheme {
+            Some(scheme) => self.instantiate(&scheme),
+            None => {
+                let names = self.scopes.iter().flat_map(|scope| scope.keys().map(String::as_str));
+                let suggestion = diagnostic::suggest(name, names);
+                self.errors.push(Error::UnboundVariable { name: name.to_string(), suggestion, span });
+                self.fresh()
+            }
+        }
+    }
+
+    /// Checks the statements of a function body or of the whole program in a new scope
+    /// holding the parameters and every name the statements define, so that definitions
+    /// can refer to later ones. Redefining a parameter keeps its type.
+    fn block(&mut self, mut scope: HashMap<String, Scheme>, stmts: &[Stmt]) {
+        let mut definitions: HashMap<String, usize> = scope.keys().map(|name| (name.clone(), 1)).collect();
+        for stmt in stmts {
+            if let StmtKind::DefStmt { id, .. } = &stmt.kind {
+                *definitions.entry(id.to_string()).or_default() += 1;
+            }
+        }
+        for name in definitions.keys() {
+            if !scope.contains_key(name) {
+                let ty = self.fresh();
+                scope.insert(name.clone(), Scheme::mono(ty));
+            }
+        }
+        self.scopes.push(scope);
+        for stmt in stmts {
+            self.stmt(stmt, &definitions);
+        }
+    }
+
+    fn stmt(&mut self, stmt: &Stmt, definitions: &HashMap<String, usize>) {
+        match &stmt.kind {
+            StmtKind::ExpStmt { exp } => {
+                self.exp(exp);
+            }
+            StmtKind::PrintStmt { print_type, exp } => {
+                let found = self.exp(exp);
+                let expected = match print_type {
+                    PrintType::PrintNum => Type::Num,
+                    PrintType::PrintBool => Type::Bool,
+                };
+                self.expect(exp, &found, &expected);
+            }
+            StmtKind::DefStmt { id, exp } => {
+                let name = id.to_string();
+                let found = self.exp(exp);
+                let expected = self.scopes.last().unwrap()[&name].ty.clone();
+                self.expect(exp, &found, &expected);
+                if definitions.get(&name) == Some(&1) {
+                    self.generalize(&name);
+                }
+            }
+        }
+    }
+
+    fn exp(&mut self, exp: &Exp) -> Type {
+        match &exp.kind {
+            ExpKind::Bool(_) => Type::Bool,
+            ExpKind::Num(_) => Type::Num,
+            ExpKind::Id(name) | ExpKind::Var { name, .. } => self.lookup(name, exp.span),
+            ExpKind::NumExp { op, args } => {
+                for arg in args {
+                    let found = self.exp(arg);
+                    self.expect(arg, &found, &Type::Num);
+                }
+                match op {
+                    NumOp::Greater | NumOp::Smaller | NumOp::Equal => Type::Bool,
+                    _ => Type::Num,
+                }
+            }
+            ExpKind::LogicalExp { args, .. } => {
+                for arg in args {
+                    let found = self.exp(arg);
+                    self.expect(arg, &found, &Type::Bool);
+                }
+                Type::Bool
+            }
+            ExpKind::IfExp { cond_exp, then_exp, else_exp } => {
+                let found = self.exp(cond_exp);
+                self.expect(cond_exp, &found, &Type::Bool);
+                let ty = self.exp(then_exp);
+                let found = self.exp(else_exp);
+                self.expect(else_exp, &found, &ty);
+                ty
+            }
+            ExpKind::FunExp(lambda) => {
+                let params: Vec<Type> = lambda.params.iter().map(|_| self.fresh()).collect();
+                let mut scope = HashMap::new();
+                for (param, ty) in lambda.params.iter().zip(&params) {
+                    scope.insert(param.to_string(), Scheme::mono(ty.clone()));
+                }
+                self.block(scope, &lambda.def_stmts);
+                let ret = self.exp(&lambda.body);
+                self.scopes.pop();
+                Type::Fun(params, Box::new(ret))
+            }
+            ExpKind::FunCall { func, args } => {
+                let func_ty = self.exp(func);
+                let arg_tys: Vec<Type> = args.iter().map(|arg| self.exp(arg)).collect();
+                match self.apply(&func_ty) {
+                    Type::Fun(params, ret) => {
+                        if params.len() != args.len() {
+                            let name = match &func.kind {
+                                ExpKind::Id(name) | ExpKind::Var { name, .. } => Some(name.clone()),
+                                _ => None,
+                            };
+                            self.errors.push(Error::Arity { name, expected: params.len(), found: args.len(), span: exp.span });
+                            return *ret;
+                        }
+                        for ((arg, found), expected) in args.iter().zip(&arg_tys).zip(&params) {
+                            self.expect(arg, found, expected);
+                        }
+                        *ret
+                    }
+                    Type::Var(_) => {
+                        let ret = self.fresh();
+                        self.expect(func, &func_ty, &Type::Fun(arg_tys, Box::new(ret.clone())));
+                        ret
+                    }
+                    ty => {
+                        let found = if ty == Type::Num { "number" } else { "boolean" };
+                        self.errors.push(Error::NotCallable { found, span: func.span });
+                        self.fresh()
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn substitute(ty: &Type, fresh: &HashMap<usize, Type>) -> Type {
+    match ty {
+        Type::Var(var) => fresh.get(var).cloned().unwrap_or(Type::Var(*var)),
+        Type::Fun(params, ret) => Type::Fun(params.iter().map(|param| substitute(param, fresh)).collect(), Box::new(substitute(ret, fresh))),
+        _ => ty.clone(),
+    }
+}
@@ -0,0 +1,298 @@
+use std::cell::RefCell;
+use std::io::Write;
+use std::rc::Rc;
+
+use crate::ast::*;
+use crate::compiler::{self, Module, Op, Proto};
+use crate::env::*;
+use crate::error::{Error, Frame};
+use crate::interpreter::{self, Context, Options, BACKTRACE_LEN};
+use crate::number::Number;
+use crate::optimize;
+use crate::resolve;
+
+#[derive(Debug)]
+pub struct Closure {
+    pub proto: Rc<Proto>,
+    pub env: Option<Rc<Scope>>,
+}
+
+pub fn run<W: Write>(program: Program, writer: &mut W) -> Result<(), Error> {
+    run_with_options(program, writer, Options::default())
+}
+
+pub fn run_with_options<W: Write>(mut program: Program, writer: &mut W, options: Options) -> Result<(), Error> {
+    if options.optimize {
+        optimize::optimize(&mut program, options.overflow);
+    }
+    let env = Rc::new(RefCell::new(Env::new()));
+    let mut ctx = Context::new(writer, options);
+    for stmt in program.stmts {
+        eval_stmt(stmt, env.clone(), &mut ctx)?;
+    }
+    Ok(())
+}
+
+/// Compiles and runs one top-level statement in `env`, returning the value of a bare expression statement.
+pub fn eval_stmt<W: Write>(mut stmt: Stmt, env: Rc<RefCell<Env>>, ctx: &mut Context<W>) -> Result<Option<Value>, Error> {
+    resolve::resolve_stmt(&mut stmt, &mut env.borrow_mut());
+    execute(Rc::new(compiler::compile_stmt(&stmt)), &env, ctx)
+}
+
+/// Runs a program loaded from a `.lspc` file.
+pub fn run_module<W: Write>(module: &Module, writer: &mut W, options: Options) -> Result<(), Error> {
+    let mut env = Env::new();
+    for name in &module.globals {
+        env.slot(name);
+    }
+    let env = Rc::new(RefCell::new(env));
+    let mut ctx = Context::new(writer, options);
+    for proto in &module.stmts {
+        execute(proto.clone(), &env, &mut ctx)?;
+    }
+    Ok(())
+}
+
+fn execute<W: Write>(proto: Rc<Proto>, env: &Rc<RefCell<Env>>, ctx: &mut Context<W>) -> Result<Option<Value>, Error> {
+    let base = ctx.frames.len();
+    let mut vm = Vm {
+        env,
+        ctx,
+        stack: Vec::new(),
+        calls: Vec::new(),
+    };
+    let result = vm.run(proto);
+    ctx.frames.truncate(base);
+    result
+}
+
+/// An active function call, or the top-level statement at the bottom of the call stack.
+struct CallFrame {
+    proto: Rc<Proto>,
+    pc: usize,
+    scope: Option<Rc<Scope>>,
+    /// A tail `and`/`or` arm whose value the result must still turn out a boolean for.
+    expect_bool: Option<(Operand, Span)>,
+}
+
+struct Vm<'a, 'w, W: Write> {
+    env: &'a Rc<RefCell<Env>>,
+    ctx: &'a mut Context<'w, W>,
+    stack: Vec<Value>,
+    calls: Vec<CallFrame>,
+}
+
+impl<W: Write> Vm<'_, '_, W> {
+    fn frame(&self) -> &CallFrame {
+        self.calls.last().unwrap()
+    }
+
+    fn pop(&mut self) -> Result<Value, Error> {
+        self.stack.pop().ok_or_else(corrupt)
+    }
+
+    fn peek(&self) -> Result<&Value, Error> {
+        self.stack.last().ok_or_else(corrupt)
+    }
+
+    fn pop_bool(&mut self) -> Result<bool, Error> {
+        match self.pop()? {
+            Value::Bool(val) => Ok(val),
+            _ => Err(corrupt()),
+        }
+    }
+
+    /// The top `count` values of the stack, bottom first.
+    fn pop_many(&mut self, count: usize) -> Result<Vec<Value>, Error> {
+        let start = self.stack.len().checked_sub(count).ok_or_else(corrupt)?;
+        Ok(self.stack.split_off(start))
+    }
+
+    fn run(&mut self, proto: Rc<Proto>) -> Result<Option<Value>, Error> {
+        self.calls.push(CallFrame {
+            proto,
+            pc: 0,
+            scope: None,
+            expect_bool: None,
+        });
+        loop {
+            let frame = self.calls.last_mut().unwrap();
+            let op = frame.proto.code[frame.pc];
+            let span = frame.proto.spans[frame.pc];
+            frame.pc += 1;
+            match op {
+                Op::Const(index) => {
+                    let val = self.frame().proto.consts[index as usize].clone();
+                    self.stack.push(Value::Num(val));
+                }
+                Op::Bool(val) => self.stack.push(Value::Bool(val)),
+                Op::LoadLocal(depth, slot) => {
+                    let scope = self.frame().scope.as_ref().unwrap();
+                    match scope.get(depth as usize, slot as usize) {
+                        Some(val) => self.stack.push(val),
+                        None => {
+                            let name = scope.name(depth as usize, slot as usize);
+                            return Err(interpreter::unbound(name, self.env, Some(scope), span));
+                        }
+                    }
+                }
+                Op::LoadGlobal(slot) => {
+                    let val = self.env.borrow().get(slot as usize);
+                    match val {
+                        Some(val) => self.stack.push(val),
+                        None => {
+                            let name = self.env.borrow().name(slot as usize).to_string();
+                            return Err(interpreter::unbound(&name, self.env, self.frame().scope.as_ref(), span));
+                        }
+                    }
+                }
+                Op::StoreLocal(depth, slot) => {
+                    let val = self.pop()?;
+                    self.frame().scope.as_ref().unwrap().set(depth as usize, slot as usize, val);
+                }
+                Op::StoreGlobal(slot) => {
+                    let val = self.pop()?;
+                    self.env.borrow_mut().set(slot as usize, val);
+                }
+                Op::CheckNum(operand) => {
+                    let val = self.peek()?;
+                    if !matches!(val, Value::Num(_)) {
+                        return Err(val.to_num(operand, span).unwrap_err());
+                    }
+                }
+                Op::CheckBool(operand) => {
+                    let val = self.peek()?;
+                    if !matches!(val, Value::Bool(_)) {
+                        return Err(val.to_bool(operand, span).unwrap_err());
+                    }
+                }
+                Op::Arith(op, argc) => {
+                    let args = self
+                        .pop_many(argc as usize)?
+                        .into_iter()
+                        .map(|arg| match arg {
+                            Value::Num(val) => Ok(val),
+                            _ => Err(corrupt()),
+                        })
+                        .collect::<Result<Vec<Number>, _>>()?;
+                    let val = interpreter::apply_num_op(op, &args, self.ctx.options.overflow, span)?;
+                    self.stack.push(val);
+                }
+                Op::Not => {
+                    let val = self.pop_bool()?;
+                    self.stack.push(Value::Bool(!val));
+                }
+                Op::Jump(target) => self.calls.last_mut().unwrap().pc = target as usize,
+                Op::JumpIfFalse(target) => {
+                    if !self.pop_bool()? {
+                        self.calls.last_mut().unwrap().pc = target as usize;
+                    }
+                }
+                Op::JumpIfTrue(target) => {
+                    if self.pop_bool()? {
+                        self.calls.last_mut().unwrap().pc = target as usize;
+                    }
+                }
+                Op::Closure(index) => {
+                    let frame = self.frame();
+                    let closure = Closure {
+                        proto: frame.proto.protos[index as usize].clone(),
+                        env: frame.scope.clone(),
+                    };
+                    self.stack.push(Value::VmClosure(Rc::new(closure)));
+                }
+                Op::CheckCall(site) => {
+                    let site = &self.frame().proto.calls[site as usize];
+                    match self.peek()? {
+                        Value::VmClosure(closure) if closure.proto.arity != site.argc => {
+                            return Err(Error::Arity {
+                                name: site.name.clone(),
+                                expected: closure.proto.arity as usize,
+                                found: site.argc as usize,
+                                span: site.span,
+                            });
+                        }
+                        Value::VmClosure(_) => {}
+                        val => {
+                            return Err(Error::NotCallable {
+                                found: val.type_name(),
+                                span: site.func_span,
+                            })
+                        }
+                    }
+                }
+                Op::Call(site) => self.call(site as usize, false)?,
+                Op::TailCall(site) => self.call(site as usize, true)?,
+                Op::PrintNum => {
+                    let val = self.pop()?;
+                    writeln!(self.ctx.writer, "{}", val.to_num(Operand::new(Operator::Print(PrintType::PrintNum), 1), span)?)?;
+                }
+                Op::PrintBool => {
+                    let val = self.pop()?;
+                    let val = val.to_bool(Operand::new(Operator::Print(PrintType::PrintBool), 1), span)?;
+                    writeln!(self.ctx.writer, "{}", if val { "#t" } else { "#f" })?;
+                }
+                Op::Return => {
+                    let frame = self.calls.pop().unwrap();
+                    if self.calls.is_empty() {
+                        return Ok(self.stack.pop());
+                    }
+                    let val = self.pop()?;
+                    let val = match frame.expect_bool {
+                        Some((operand, span)) => Value::Bool(val.to_bool(operand, span)?),
+                        None => val,
+                    };
+                    self.ctx.frames.pop();
+                    self.stack.push(val);
+                }
+            }
+        }
+    }
+
+    /// Enters the callee sitting below the call site's arguments. A tail call first
+    /// leaves the current frame, so it runs in constant stack.
+    fn call(&mut self, site: usize, tail: bool) -> Result<(), Error> {
+        let site = self.frame().proto.calls[site].clone();
+        let args = self.pop_many(site.argc as usize)?;
+        let closure = match self.pop()? {
+            Value::VmClosure(closure) if closure.proto.arity == site.argc => closure,
+            _ => return Err(corrupt()),
+        };
+        let scope = Rc::new(Scope::new(closure.proto.slots.clone(), closure.env.clone()));
+        for (slot, arg) in args.into_iter().enumerate() {
+            scope.set(0, slot, arg);
+        }
+
+        let mut expect_bool = None;
+        if tail {
+            expect_bool = self.calls.pop().unwrap().expect_bool;
+            self.ctx.frames.pop();
+        }
+        if self.ctx.frames.len() >= self.ctx.options.max_depth {
+            return Err(Error::RecursionLimit {
+                limit: self.ctx.options.max_depth,
+                backtrace: self.ctx.frames.iter().rev().take(BACKTRACE_LEN).cloned().collect(),
+                span: site.span,
+            });
+        }
+        self.ctx.frames.push(Frame {
+            name: site.name,
+            span: site.span,
+        });
+        self.calls.push(CallFrame {
+            proto: closure.proto.clone(),
+            pc: 0,
+            scope: Some(scope),
+            expect_bool: site.check.or(expect_bool),
+        });
+        Ok(())
+    }
+}
+
+/// Compiled code checks every value before it is consumed, so only a tampered `.lspc`
+/// file can leave the stack short or holding the wrong kind of value.
+fn corrupt() -> Error {
+    Error::Bytecode {
+        message: "the bytecode is corrupt".to_string(),
+    }
+}